@@ -1,78 +1,27 @@
-#[allow(dead_code)]
-fn day_2_part_1(input: &str, noun: usize, verb: usize) -> Vec<usize> {
-    let mut memory = parse(input);
-    exec(&mut memory, noun, verb);
-    memory
-}
+use intcode::{Computer, Status};
+use intcode::image::Image;
+use intcode::sweep::Sweep;
 
 #[allow(dead_code)]
-fn day_2_part_2(input: &str, target: usize) -> usize {
-    let initial_memory = parse(input);
-
-    for noun in 0..=99 {
-        for verb in 0..=99 {
-            let mut memory = initial_memory.clone();
-            exec(&mut memory, noun, verb);
-
-            if memory[0] == target {
-                return 100 * noun + verb;
-            }
-        }
-    }
-
-    unimplemented!()
-}
-
-fn exec(memory: &mut Vec<usize>, noun: usize, verb: usize) {
-    let mut pointer = 0;
-
-    memory[1] = noun;
-    memory[2] = verb;
-
-    loop {
-        let advance = match memory[pointer] {
-            1 => {
-                binay_instruction_exec(memory, pointer, BinaryInstruction::Add);
-                4
-            },
-            2 => {
-                binay_instruction_exec(memory, pointer, BinaryInstruction::Multiply);
-                4
-            },
-            99 => break,
-            _ => unimplemented!()
-        };
-
-        pointer += advance;
-    };
-}
-
-enum BinaryInstruction {
-    Add,
-    Multiply
-}
+fn day_2_part_1(input: &str, noun: usize, verb: usize) -> Vec<usize> {
+    let mut computer = Computer::new(input, vec![])
+        .and_then(|computer| computer.with_patches(&[(1, noun as i32), (2, verb as i32)]))
+        .expect("Could not load program.");
 
-fn parse(input: &str) -> Vec<usize> {
-    input.split(',')
-        .filter(|x| !x.is_empty())
-        .map(|x| {
-            x.trim().parse::<usize>().expect(&format!("Could not parse '{}' as usize.", x))
-        })
-        .collect()
+    computer.exec();
+    computer.memory().iter().map(|&x| x as usize).collect()
 }
 
-fn binay_instruction_exec(memory: &mut Vec<usize>, pointer: usize, opcode: BinaryInstruction) {
-    let x_addr = memory[pointer + 1];
-    let y_addr = memory[pointer + 2];
-    let output_addr = memory[pointer + 3];
-
-    let x = memory[x_addr];
-    let y = memory[y_addr];
-
-    match opcode {
-        BinaryInstruction::Add => memory[output_addr] = x + y,
-        BinaryInstruction::Multiply => memory[output_addr] = x * y,
-    }
+#[allow(dead_code)]
+fn day_2_part_2(input: &str, target: usize) -> Option<usize> {
+    let image = Image::parse(input).expect("Could not load program.");
+
+    Sweep::with_image(image)
+        .patch(1, 0..=99)
+        .patch(2, 0..=99)
+        .first(|computer| computer.status() == Status::Halted && computer.memory()[0] == target as i32)
+        .expect("Could not patch noun and verb.")
+        .map(|found| (100 * found[0] + found[1]) as usize)
 }
 
 #[cfg(test)]
@@ -95,6 +44,7 @@ mod tests {
 
     #[test]
     fn day_2_part_2_test_input() {
-        assert_eq!(day_2_part_2(include_str!("input"), 19690720), 8226);
+        assert_eq!(day_2_part_2(include_str!("input"), 19690720), Some(8226));
+        assert_eq!(day_2_part_2(include_str!("input"), 1), None);
     }
 }
@@ -0,0 +1,26 @@
+# The examples from part 1, which add input and output instructions and
+# param modes.
+
+[case "outputs its input"]
+program = 3,0,4,0,99
+input = 1
+output = 1
+memory[0] = 1
+status = halted
+
+[case "multiplies with an immediate param"]
+program = 1002,4,3,4,33
+output =
+memory[4] = 99
+status = halted
+
+[case "adds a negative immediate param"]
+program = 1101,100,-1,4,0
+output =
+memory[4] = 99
+
+[case "test input"]
+program_file = ../src/input
+input = 1
+output = 0,0,0,0,0,0,0,0,0,9219874
+status = halted
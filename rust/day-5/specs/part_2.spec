@@ -0,0 +1,99 @@
+# The examples from part 2, which add jumps and comparisons.
+
+[case "equal to 8, position mode"]
+program = 3,9,8,9,10,9,4,9,99,-1,8
+input = 8
+output = 1
+
+[case "not equal to 8, position mode"]
+program = 3,9,8,9,10,9,4,9,99,-1,8
+input = 7
+output = 0
+
+[case "less than 8, position mode"]
+program = 3,9,7,9,10,9,4,9,99,-1,8
+input = 7
+output = 1
+
+[case "not less than 8, position mode"]
+program = 3,9,7,9,10,9,4,9,99,-1,8
+input = 9
+output = 0
+
+[case "equal to 8, immediate mode"]
+program = 3,3,1108,-1,8,3,4,3,99
+input = 8
+output = 1
+
+[case "not equal to 8, immediate mode"]
+program = 3,3,1108,-1,8,3,4,3,99
+input = 7
+output = 0
+
+[case "less than 8, immediate mode"]
+program = 3,3,1107,-1,8,3,4,3,99
+input = 7
+output = 1
+
+[case "not less than 8, immediate mode"]
+program = 3,3,1107,-1,8,3,4,3,99
+input = 9
+output = 0
+
+[case "jumps on nonzero, position mode"]
+program = 3,12,6,12,15,1,13,14,13,4,13,99,-1,0,1,9
+input = 1
+output = 1
+
+[case "jumps on zero, position mode"]
+program = 3,12,6,12,15,1,13,14,13,4,13,99,-1,0,1,9
+input = 0
+output = 0
+
+# Not one of the puzzle's examples, but no other case uses jump-if-true with
+# both params in position mode.
+[case "jumps if true, position mode"]
+program = 3,11,5,11,12,104,0,99,104,1,99,-1,8
+input = 1
+output = 1
+
+[case "jumps if true, position mode, not taken"]
+program = 3,11,5,11,12,104,0,99,104,1,99,-1,8
+input = 0
+output = 0
+
+[case "jumps on nonzero, immediate mode"]
+program = 3,3,1105,-1,9,1101,0,0,12,4,12,99,1
+input = 1
+output = 1
+
+[case "jumps on zero, immediate mode"]
+program = 3,3,1105,-1,9,1101,0,0,12,4,12,99,1
+input = 0
+output = 0
+
+[case "large example, below 8"]
+program_file = ../src/large_example
+input = 7
+output = 999
+
+[case "large example, equal to 8"]
+program_file = ../src/large_example
+input = 8
+output = 1000
+
+[case "large example, above 8"]
+program_file = ../src/large_example
+input = 9
+output = 1001
+
+[case "large example, waiting for input"]
+program_file = ../src/large_example
+output =
+status = awaiting input
+
+[case "test input"]
+program_file = ../src/input
+input = 5
+output = 5893654
+status = halted
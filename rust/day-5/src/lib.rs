@@ -1,346 +1,12 @@
-use std::convert::TryFrom;
-use std::collections::HashSet;
-
-#[derive(Debug)]
-struct Computer {
-    pub memory: Vec<i32>,
-    input: Vec<i32>,
-    last_output: Option<i32>
-}
-
-#[allow(dead_code)]
-impl Computer {
-    fn new(raw_memory: &str, input: Vec<i32>) -> Result<Self, String> {
-        let memory = raw_memory.split(',')
-            .filter(|x| !x.is_empty())
-            .map(|x| {
-                x.trim().parse::<i32>()
-                    .map_err(|e| format!("Failed to parse memory as i32: {}", e))
-            })
-            .collect::<Result<Vec<i32>, String>>()?;
-
-        Ok(Computer {
-            memory,
-            input,
-            last_output: None
-        })
-    }
-
-    fn exec(&mut self) -> Option<i32> {
-        let mut pointer = 0;
-
-        loop {
-            let opcode_with_param_modes = OpcodeWithParamModes::try_from(self.memory[pointer])
-                .expect("Unexpected opcode encountered!");
-
-            match opcode_with_param_modes.exec(self, pointer) {
-                ExecResult::Success(next_pointer) => pointer = next_pointer,
-                ExecResult::Halt => break,
-                ExecResult::Failed(err) => panic!(err),
-            }
-        };
-
-        self.last_output
-    }
-
-    fn read(&mut self) -> i32 {
-        self.input.remove(0)
-    }
-
-    fn write(&mut self, value: i32) -> () {
-        self.last_output = Some(value);
-    }
-}
-
-#[derive(Debug)]
-struct OpcodeWithParamModes {
-    opcode: Opcode,
-    param_modes: Vec<ParamMode>
-}
-
-impl TryFrom<i32> for OpcodeWithParamModes {
-    type Error = String;
-
-    fn try_from(value: i32) -> Result<Self, Self::Error> {
-        let opcode = Opcode::try_from(value % 100)?;
-        let writable_params = opcode.writable_params();
-
-        let first_param_mode = if writable_params.contains(&1) {
-            ParamMode::ImmediateMode
-        } else {
-            ParamMode::try_from((value / 100) % 10)?
-        };
-        let second_param_mode = if writable_params.contains(&2) {
-            ParamMode::ImmediateMode
-        } else {
-            ParamMode::try_from((value / 1000) % 10)?
-        };
-        let third_param_mode = if writable_params.contains(&3) {
-            ParamMode::ImmediateMode
-        } else {
-            ParamMode::try_from((value / 100000) % 10)?
-        };
-        let param_modes = vec![first_param_mode, second_param_mode, third_param_mode];
-
-        Ok(OpcodeWithParamModes {
-            opcode,
-            param_modes
-        })
-    }
-}
-
-#[derive(Debug)]
-enum ExecResult<E> {
-    Success(usize),
-    Halt,
-    Failed(E),
-}
-
-impl OpcodeWithParamModes {
-    fn exec(&self, computer: &mut Computer, opcode_pos: usize) -> ExecResult<String> {
-        let params: Params = self.extract_params(&computer.memory, opcode_pos)
-            .expect("Unable to extract params!");
-
-        let next_pointer = opcode_pos + self.opcode.num_params() + 1;
-        let res = self.opcode.exec(params);
-
-        match res {
-            OpcodeResult::NoOp => ExecResult::Success(next_pointer),
-            OpcodeResult::WriteToOutput(value) => {
-                computer.write(value);
-                ExecResult::Success(next_pointer)
-            },
-            OpcodeResult::WriteValueToMemory(value, to) => {
-                computer.memory[to] = value;
-                ExecResult::Success(next_pointer)
-            },
-            OpcodeResult::WriteInputToMemory(to) => {
-                computer.memory[to] = computer.read();
-                ExecResult::Success(next_pointer)
-            },
-            OpcodeResult::JumpTo(to) => ExecResult::Success(to),
-            OpcodeResult::Halt => ExecResult::Halt,
-            OpcodeResult::Failed(err) => ExecResult::Failed(err)
-        }
-    }
-
-    fn extract_params(&self, memory: &Vec<i32>, opcode_pos: usize) -> Result<Params, String> {
-        let num_params = self.opcode.num_params();
-
-        let mut params = Vec::with_capacity(3);
-        params.resize(3, None);
-
-        for i in 0..num_params {
-            let raw = memory[opcode_pos + i + 1];
-
-            params[i] = match self.param_modes[i] {
-                ParamMode::PositionMode => Some(memory[raw as usize]),
-                ParamMode::ImmediateMode => Some(raw)
-            };
-        }
-
-        Params::try_from(params)
-    }
-}
-
-#[derive(Debug)]
-enum ParamMode {
-    PositionMode,
-    ImmediateMode,
-}
-
-impl TryFrom<i32> for ParamMode {
-    type Error = String;
-
-    fn try_from(value: i32) -> Result<Self, Self::Error> {
-        match value {
-            0 => Ok(ParamMode::PositionMode),
-            1 => Ok(ParamMode::ImmediateMode),
-            code => Err(format!("Unknown param mode '{}' encountered.", code))
-        }
-    }
-}
-
-struct Params {
-    first: Option<i32>,
-    second: Option<i32>,
-    third: Option<i32>,
-}
-
-impl TryFrom<Vec<Option<i32>>> for Params {
-    type Error = String;
-
-    fn try_from(value: Vec<Option<i32>>) -> Result<Self, Self::Error> {
-        if value.len() != 3 {
-            return Err("Params can only be constructed from a Vec of exactly 3 in length.".to_string());
-        }
-
-        Ok(Params {
-            first: value[0],
-            second: value[1],
-            third: value[2]
-        })
-    }
-}
-
-#[derive(Debug)]
-enum Opcode {
-    Add = 1,
-    Multiply = 2,
-    Input = 3,
-    Output = 4,
-    JumpIfTrue = 5,
-    JumpIfFalse = 6,
-    LessThan = 7,
-    Equals = 8,
-    Halt = 99
-}
-
-impl TryFrom<i32> for Opcode {
-    type Error = String;
-
-    fn try_from(value: i32) -> Result<Self, Self::Error> {
-        match value {
-            1 => Ok(Opcode::Add),
-            2 => Ok(Opcode::Multiply),
-            3 => Ok(Opcode::Input),
-            4 => Ok(Opcode::Output),
-            5 => Ok(Opcode::JumpIfTrue),
-            6 => Ok(Opcode::JumpIfFalse),
-            7 => Ok(Opcode::LessThan),
-            8 => Ok(Opcode::Equals),
-            99 => Ok(Opcode::Halt),
-            code => Err(format!("Unknown opcode '{}' encountered.", code))
-        }
-    }
-}
-
-impl Opcode {
-    fn num_params(&self) -> usize {
-        match self {
-            Opcode::Add => 3,
-            Opcode::Multiply => 3,
-            Opcode::Input => 1,
-            Opcode::Output => 1,
-            Opcode::JumpIfTrue => 2,
-            Opcode::JumpIfFalse => 2,
-            Opcode::LessThan => 3,
-            Opcode::Equals => 3,
-            Opcode::Halt => 0,
-        }
-    }
-
-
-    fn writable_params(&self) -> HashSet<usize> {
-        let mut params = HashSet::new();
-
-        // Param indices are 1 based.
-        match self {
-            Opcode::Add => params.insert(3),
-            Opcode::Multiply => params.insert(3),
-            Opcode::Input => params.insert(1),
-            Opcode::Output => false,
-            Opcode::JumpIfTrue => false,
-            Opcode::JumpIfFalse => false,
-            Opcode::LessThan => params.insert(3),
-            Opcode::Equals => params.insert(3),
-            Opcode::Halt => false,
-        };
-
-        params
-    }
-
-    fn exec(&self, params: Params) -> OpcodeResult<String> {
-        match self {
-            Opcode::Add => {
-                match (params.first, params.second, params.third) {
-                    (Some(a), Some(b), Some(res)) => {
-                        OpcodeResult::WriteValueToMemory(a + b, res as usize)
-                    },
-                    _ => OpcodeResult::Failed("Add expected 3 params.".to_string())
-                }
-            },
-            Opcode::Multiply => {
-                match (params.first, params.second, params.third) {
-                    (Some(a), Some(b), Some(res)) => {
-                        OpcodeResult::WriteValueToMemory(a * b, res as usize)
-                    },
-                    _ => OpcodeResult::Failed("Multiply expected 3 params.".to_string())
-                }
-            },
-            Opcode::Input => {
-                match params.first {
-                    Some(res) => {
-                        OpcodeResult::WriteInputToMemory(res as usize)
-                    },
-                    _ => OpcodeResult::Failed("Input expected 1 param.".to_string())
-                }
-            },
-            Opcode::Output => {
-                match params.first {
-                    Some(value) => {
-                        OpcodeResult::WriteToOutput(value)
-                    },
-                    _ => OpcodeResult::Failed("Output expected 1 param.".to_string())
-                }
-            },
-            Opcode::JumpIfTrue => {
-                match (params.first, params.second) {
-                    (Some(value), Some(jump_to)) if value != 0 => {
-                        OpcodeResult::JumpTo(jump_to as usize)
-                    },
-                    (Some(_), Some(_)) => {
-                        OpcodeResult::NoOp
-                    },
-                    _ => OpcodeResult::Failed("JumpIfTrue expected 2 params.".to_string())
-                }
-            },
-            Opcode::JumpIfFalse => {
-                match (params.first, params.second) {
-                    (Some(value), Some(jump_to)) if value == 0 => {
-                        OpcodeResult::JumpTo(jump_to as usize)
-                    },
-                    (Some(_), Some(_)) => {
-                        OpcodeResult::NoOp
-                    },
-                    _ => OpcodeResult::Failed("JumpIfFalse expected 2 params.".to_string())
-                }
-            },
-            Opcode::LessThan => {
-                match (params.first, params.second, params.third) {
-                    (Some(a), Some(b), Some(res)) => {
-                        OpcodeResult::WriteValueToMemory((a < b) as i32, res as usize)
-                    },
-                    _ => OpcodeResult::Failed("LessThan expected 3 params.".to_string())
-                }
-            },
-            Opcode::Equals => {
-                match (params.first, params.second, params.third) {
-                    (Some(a), Some(b), Some(res)) => {
-                        OpcodeResult::WriteValueToMemory((a == b) as i32, res as usize)
-                    },
-                    _ => OpcodeResult::Failed("Equals expected 3 params.".to_string())
-                }
-            },
-            Opcode::Halt => OpcodeResult::Halt
-        }
-    }
-}
-
-#[derive(Debug)]
-enum OpcodeResult<E> {
-    NoOp,
-    WriteToOutput(i32),
-    WriteValueToMemory(i32, usize),
-    WriteInputToMemory(usize),
-    JumpTo(usize),
-    Halt,
-    Failed(E)
-}
-
 #[cfg(test)]
 mod tests {
-    use super::Computer;
+    use intcode::Computer;
+    use intcode::coverage::Coverage;
+    use intcode::decompile::Decompiler;
+    use intcode::minimize::minimize;
+    use intcode::optimize::optimize;
+    use intcode::spec;
+    use std::path::Path;
 
     #[test]
     fn day_5_part_1_examples() {
@@ -389,4 +55,103 @@ mod tests {
     fn day_5_part_2_test_input() {
         assert_eq!(Computer::new(include_str!("input"), vec![5]).unwrap().exec(), Some(5893654));
     }
+
+    #[test]
+    fn day_5_specs() {
+        let outcomes = spec::run_dir(&Path::new(env!("CARGO_MANIFEST_DIR")).join("specs")).unwrap();
+
+        let failures: Vec<String> = outcomes.iter()
+            .filter(|outcome| outcome.failure.is_some())
+            .map(|outcome| outcome.to_string())
+            .collect();
+
+        assert!(!outcomes.is_empty());
+        assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+    }
+
+    #[test]
+    fn day_5_decompiles_test_input() {
+        let computer = Computer::new(include_str!("input"), vec![]).unwrap();
+
+        // The instruction at 6 is patched with the input, and then either
+        // runs the first set of checks from 10 or jumps to the second at 238.
+        let code = Decompiler::default().entry(10).entry(238).decompile(&computer.memory().to_vec());
+
+        assert!(code.starts_with("m[225] = input()\nm[6] = m[225] + m[6]\n// unknown instruction 1100\noutput(0)\n"));
+        assert!(code.contains("\nhalt()\n// never jumps to 99999\nif (m[227] != 0) goto L99999\n"));
+        assert!(code.contains("\nm[0] = 294 + 0\nm[225] = m[225] + m[225]\nm[0] = 314 + 0\n"));
+        assert!(code.contains("\nm[224] = 226 < m[226]\nm[223] = 2 * m[223]\nif (m[224] != 0) {\n    m[223] = m[223] + 1\n}\n"));
+        assert!(code.ends_with("output(m[223])\nhalt()\n"));
+    }
+
+    #[test]
+    fn day_5_optimized_examples_match() {
+        let examples = [
+            ("3,0,4,0,99", vec![1, 5]),
+            ("1002,4,3,4,33", vec![1]),
+            ("3,9,8,9,10,9,4,9,99,-1,8", vec![7, 8]),
+            ("3,9,7,9,10,9,4,9,99,-1,8", vec![7, 9]),
+            ("3,3,1108,-1,8,3,4,3,99", vec![7, 8]),
+            ("3,3,1107,-1,8,3,4,3,99", vec![7, 9]),
+            ("3,12,6,12,15,1,13,14,13,4,13,99,-1,0,1,9", vec![0, 1]),
+            ("3,3,1105,-1,9,1101,0,0,12,4,12,99,1", vec![0, 1]),
+            (include_str!("large_example"), vec![7, 8, 9]),
+            (include_str!("input"), vec![1, 5])
+        ];
+
+        for (program, inputs) in examples.iter() {
+            let memory = Computer::new(program, vec![]).unwrap().memory().to_vec();
+            let optimized = optimize(&memory).memory;
+
+            for &input in inputs {
+                let mut before = Computer::with_memory(memory.clone(), vec![input]);
+                let mut after = Computer::with_memory(optimized.clone(), vec![input]);
+
+                assert_eq!(before.run(), after.run());
+                assert_eq!(before.outputs(), after.outputs());
+            }
+        }
+
+        // The test input patches the instruction at 6 with the input before
+        // running it, so is left alone.
+        let memory = Computer::new(include_str!("input"), vec![]).unwrap().memory().to_vec();
+        assert_eq!(
+            optimize(&memory).refused,
+            Some("The word at 6 is not an instruction: Unknown opcode '0' encountered.".to_string())
+        );
+
+        let memory = Computer::new(include_str!("large_example"), vec![]).unwrap().memory().to_vec();
+        assert!(!optimize(&memory).rewrites.is_empty());
+    }
+
+    #[test]
+    fn day_5_minimizes_test_input() {
+        // Stands in for a bug that only shows up in the final diagnostic code.
+        let fails = |memory: &[i32]| {
+            let mut computer = Computer::with_memory(memory.to_vec(), vec![1]);
+            let _ = computer.run_for(10_000);
+            computer.last_output() == Some(9219874)
+        };
+
+        let memory = Computer::new(include_str!("input"), vec![]).unwrap().memory().to_vec();
+        let minimized = minimize(&memory, fails);
+        let reloaded = Computer::new(&minimized.to_string(), vec![]).unwrap().memory().to_vec();
+
+        assert!(fails(&reloaded));
+        assert_eq!(reloaded, minimized.memory);
+        assert!(minimized.memory.len() < memory.len());
+    }
+
+    #[test]
+    fn day_5_specs_coverage() {
+        let mut coverage = Coverage::default();
+
+        for (_, case) in spec::load_dir(&Path::new(env!("CARGO_MANIFEST_DIR")).join("specs")).unwrap() {
+            coverage.merge(&Coverage::measure(&case.program, &[case.input], case.budget));
+        }
+
+        let missing: Vec<String> = coverage.missing_signatures().iter().map(|signature| signature.to_string()).collect();
+        assert_eq!(missing, Vec::<String>::new());
+        assert!(coverage.to_string().contains("Executed 28 of 28 signatures, missing:\n"));
+    }
 }
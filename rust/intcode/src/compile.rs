@@ -0,0 +1,675 @@
+//! Compiling a small expression language down to Intcode.
+//!
+//! A program is a list of statements, where `#` starts a comment:
+//!
+//! ```text
+//! # Outputs the sum of the numbers from the input down to 1.
+//! n = input();
+//! total = 0;
+//! while n > 0 {
+//!     total = total + n;
+//!     n = n - 1;
+//! }
+//! output(total);
+//! ```
+//!
+//! * `name = expr;` assigns to a variable, creating it the first time. A
+//!   variable can only be used after its first assignment.
+//! * `output(expr);` outputs a value.
+//! * `if expr { ... }`, optionally followed by `else { ... }` or
+//!   `else if ...`, runs a block when the value is not 0.
+//! * `while expr { ... }` runs a block for as long as the value is not 0.
+//!
+//! Expressions are made of numbers, variables, `input()`, parentheses, unary
+//! `-`, `*`, `+`, `-` and the comparisons `==`, `!=`, `<`, `<=`, `>` and
+//! `>=`, from the tightest binding to the loosest. Comparisons give 1 or 0.
+//!
+//! The program halts after its last statement. Every variable, and every
+//! temporary value used while working out an expression, has its own word
+//! in a data segment after the code, so the code never writes to itself.
+
+use std::fmt;
+
+use crate::decoder::Opcode;
+use crate::format::Position;
+
+/// Why a program could not be compiled.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompileError {
+    pub position: Position,
+    pub reason: String
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "At {}: {}", self.position, self.reason)
+    }
+}
+
+/// A compiled program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Compiled {
+    /// The program image: the code, then the data segment.
+    pub memory: Vec<i32>,
+    /// Where the code ends and the data segment starts.
+    pub code_len: usize,
+    /// The address of each variable, in the order they were created.
+    pub variables: Vec<(String, usize)>
+}
+
+/// Compiles `source`, see the module docs for the language.
+pub fn compile(source: &str) -> Result<Compiled, CompileError> {
+    let tokens = lex(source)?;
+    let mut parser = Parser { tokens, next: 0 };
+
+    let mut statements = Vec::new();
+    while parser.peek().is_some() {
+        statements.push(parser.statement()?);
+    }
+
+    let mut generator = Generator::default();
+    generator.block(&statements)?;
+    generator.code.push(Word::Value(Opcode::Halt as i32));
+
+    Ok(generator.finish())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i32),
+    Name(String),
+    Symbol(&'static str)
+}
+
+const SYMBOLS: [&str; 15] = ["==", "!=", "<=", ">=", "<", ">", "=", "+", "-", "*", "(", ")", "{", "}", ";"];
+
+fn lex(source: &str) -> Result<Vec<(Token, Position)>, CompileError> {
+    let mut tokens = Vec::new();
+
+    for (i, line) in source.lines().enumerate() {
+        let line = line.split('#').next().unwrap();
+        let mut column = 0;
+
+        while column < line.len() {
+            let rest = &line[column..];
+            let position = Position::Text { line: i + 1, column: column + 1 };
+            let c = rest.chars().next().unwrap();
+
+            let len = if c.is_whitespace() {
+                c.len_utf8()
+            } else if c.is_ascii_digit() {
+                let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+                let number = rest[..digits].parse().map_err(|err| CompileError {
+                    position,
+                    reason: format!("Failed to parse '{}' as i32: {}", &rest[..digits], err)
+                })?;
+
+                tokens.push((Token::Number(number), position));
+                digits
+            } else if c.is_ascii_alphabetic() || c == '_' {
+                let len = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+                tokens.push((Token::Name(rest[..len].to_string()), position));
+                len
+            } else {
+                let symbol = SYMBOLS.iter().find(|symbol| rest.starts_with(*symbol)).ok_or_else(|| CompileError {
+                    position,
+                    reason: format!("Unexpected '{}'.", c)
+                })?;
+
+                tokens.push((Token::Symbol(symbol), position));
+                symbol.len()
+            };
+
+            column += len;
+        }
+    }
+
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Equals,
+    NotEquals,
+    LessThan,
+    LessOrEqual,
+    GreaterThan,
+    GreaterOrEqual
+}
+
+#[derive(Debug)]
+enum Expr {
+    Number(i32),
+    Variable(String, Position),
+    Input,
+    Negate(Box<Expr>, Position),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>, Position)
+}
+
+#[derive(Debug)]
+enum Statement {
+    Assign(String, Expr),
+    Output(Expr),
+    If(Expr, Vec<Statement>, Vec<Statement>),
+    While(Expr, Vec<Statement>)
+}
+
+struct Parser {
+    tokens: Vec<(Token, Position)>,
+    next: usize
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(token, _)| token)
+    }
+
+    // Where the next token is, or where the last one was at the end.
+    fn position(&self) -> Position {
+        self.tokens.get(self.next).or_else(|| self.tokens.last())
+            .map(|&(_, position)| position)
+            .unwrap_or(Position::Text { line: 1, column: 1 })
+    }
+
+    fn error<T>(&self, expected: &str) -> Result<T, CompileError> {
+        let found = match self.peek() {
+            Some(Token::Number(number)) => format!("'{}'", number),
+            Some(Token::Name(name)) => format!("'{}'", name),
+            Some(Token::Symbol(symbol)) => format!("'{}'", symbol),
+            None => "the end of the program".to_string()
+        };
+
+        Err(CompileError { position: self.position(), reason: format!("Expected {} but found {}.", expected, found) })
+    }
+
+    fn eat(&mut self, symbol: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Symbol(s)) if *s == symbol) {
+            self.next += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), CompileError> {
+        if self.eat(symbol) {
+            Ok(())
+        } else {
+            self.error(&format!("'{}'", symbol))
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        if self.peek() == Some(&Token::Name(keyword.to_string())) {
+            self.next += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn statement(&mut self) -> Result<Statement, CompileError> {
+        if self.keyword("if") {
+            return self.if_statement();
+        }
+
+        if self.keyword("while") {
+            let condition = self.expr()?;
+            return Ok(Statement::While(condition, self.block()?));
+        }
+
+        if self.keyword("output") {
+            self.expect("(")?;
+            let value = self.expr()?;
+            self.expect(")")?;
+            self.expect(";")?;
+            return Ok(Statement::Output(value));
+        }
+
+        match self.peek().cloned() {
+            Some(Token::Name(name)) if !is_keyword(&name) => {
+                self.next += 1;
+                self.expect("=")?;
+                let value = self.expr()?;
+                self.expect(";")?;
+                Ok(Statement::Assign(name, value))
+            },
+            _ => self.error("a statement")
+        }
+    }
+
+    fn if_statement(&mut self) -> Result<Statement, CompileError> {
+        let condition = self.expr()?;
+        let then = self.block()?;
+
+        let otherwise = if !self.keyword("else") {
+            Vec::new()
+        } else if self.keyword("if") {
+            vec![self.if_statement()?]
+        } else {
+            self.block()?
+        };
+
+        Ok(Statement::If(condition, then, otherwise))
+    }
+
+    fn block(&mut self) -> Result<Vec<Statement>, CompileError> {
+        self.expect("{")?;
+
+        let mut statements = Vec::new();
+        while !self.eat("}") {
+            if self.peek().is_none() {
+                return self.error("'}'");
+            }
+            statements.push(self.statement()?);
+        }
+
+        Ok(statements)
+    }
+
+    fn expr(&mut self) -> Result<Expr, CompileError> {
+        let left = self.sum()?;
+        let position = self.position();
+
+        let op = match self.peek() {
+            Some(Token::Symbol("==")) => BinaryOp::Equals,
+            Some(Token::Symbol("!=")) => BinaryOp::NotEquals,
+            Some(Token::Symbol("<")) => BinaryOp::LessThan,
+            Some(Token::Symbol("<=")) => BinaryOp::LessOrEqual,
+            Some(Token::Symbol(">")) => BinaryOp::GreaterThan,
+            Some(Token::Symbol(">=")) => BinaryOp::GreaterOrEqual,
+            _ => return Ok(left)
+        };
+        self.next += 1;
+
+        let right = self.sum()?;
+        Ok(Expr::Binary(op, Box::new(left), Box::new(right), position))
+    }
+
+    fn sum(&mut self) -> Result<Expr, CompileError> {
+        let mut left = self.product()?;
+
+        loop {
+            let position = self.position();
+            let op = if self.eat("+") {
+                BinaryOp::Add
+            } else if self.eat("-") {
+                BinaryOp::Subtract
+            } else {
+                return Ok(left);
+            };
+
+            left = Expr::Binary(op, Box::new(left), Box::new(self.product()?), position);
+        }
+    }
+
+    fn product(&mut self) -> Result<Expr, CompileError> {
+        let mut left = self.unary()?;
+
+        loop {
+            let position = self.position();
+            if !self.eat("*") {
+                return Ok(left);
+            }
+
+            left = Expr::Binary(BinaryOp::Multiply, Box::new(left), Box::new(self.unary()?), position);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, CompileError> {
+        let position = self.position();
+        if self.eat("-") {
+            return Ok(Expr::Negate(Box::new(self.unary()?), position));
+        }
+
+        if self.eat("(") {
+            let inner = self.expr()?;
+            self.expect(")")?;
+            return Ok(inner);
+        }
+
+        match self.peek().cloned() {
+            Some(Token::Number(number)) => {
+                self.next += 1;
+                Ok(Expr::Number(number))
+            },
+            Some(Token::Name(ref name)) if name == "input" => {
+                self.next += 1;
+                self.expect("(")?;
+                self.expect(")")?;
+                Ok(Expr::Input)
+            },
+            Some(Token::Name(name)) if !is_keyword(&name) => {
+                self.next += 1;
+                Ok(Expr::Variable(name, position))
+            },
+            _ => self.error("a value")
+        }
+    }
+}
+
+fn is_keyword(name: &str) -> bool {
+    ["if", "else", "while", "input", "output"].contains(&name)
+}
+
+// A word of code, before the data segment has been placed.
+#[derive(Debug, Clone, Copy)]
+enum Word {
+    Value(i32),
+    Variable(usize),
+    Temp(usize),
+    Label(usize)
+}
+
+// Where an instruction gets a value from, or puts one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operand {
+    Immediate(i32),
+    Variable(usize),
+    Temp(usize),
+    Label(usize)
+}
+
+#[derive(Debug, Default)]
+struct Generator {
+    code: Vec<Word>,
+    variables: Vec<String>,
+    labels: Vec<Option<usize>>,
+    temps: usize,
+    max_temps: usize
+}
+
+impl Generator {
+    fn emit(&mut self, opcode: Opcode, reads: &[Operand], write: Option<Operand>) {
+        let modes: i32 = reads.iter().enumerate()
+            .map(|(i, operand)| match operand {
+                Operand::Immediate(_) | Operand::Label(_) => 10i32.pow(i as u32 + 2),
+                _ => 0
+            })
+            .sum();
+
+        self.code.push(Word::Value(opcode as i32 + modes));
+
+        for operand in reads.iter().chain(write.iter()) {
+            self.code.push(match *operand {
+                Operand::Immediate(value) => Word::Value(value),
+                Operand::Variable(index) => Word::Variable(index),
+                Operand::Temp(index) => Word::Temp(index),
+                Operand::Label(label) => Word::Label(label)
+            });
+        }
+    }
+
+    fn label(&mut self) -> usize {
+        self.labels.push(None);
+        self.labels.len() - 1
+    }
+
+    fn place(&mut self, label: usize) {
+        self.labels[label] = Some(self.code.len());
+    }
+
+    fn jump(&mut self, label: usize) {
+        self.emit(Opcode::JumpIfTrue, &[Operand::Immediate(1), Operand::Label(label)], None);
+    }
+
+    fn temp(&mut self) -> Operand {
+        self.temps += 1;
+        self.max_temps = self.max_temps.max(self.temps);
+        Operand::Temp(self.temps - 1)
+    }
+
+    fn block(&mut self, statements: &[Statement]) -> Result<(), CompileError> {
+        for statement in statements {
+            self.statement(statement)?;
+            self.temps = 0;
+        }
+
+        Ok(())
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Result<(), CompileError> {
+        match statement {
+            Statement::Assign(name, value) => {
+                let value = self.expr(value)?;
+                let index = match self.variables.iter().position(|variable| variable == name) {
+                    Some(index) => index,
+                    None => {
+                        self.variables.push(name.clone());
+                        self.variables.len() - 1
+                    }
+                };
+
+                self.emit(Opcode::Add, &[value, Operand::Immediate(0)], Some(Operand::Variable(index)));
+            },
+            Statement::Output(value) => {
+                let value = self.expr(value)?;
+                self.emit(Opcode::Output, &[value], None);
+            },
+            Statement::If(condition, then, otherwise) => {
+                let (else_label, end_label) = (self.label(), self.label());
+
+                let condition = self.expr(condition)?;
+                self.emit(Opcode::JumpIfFalse, &[condition, Operand::Label(else_label)], None);
+                self.temps = 0;
+                self.block(then)?;
+
+                if otherwise.is_empty() {
+                    self.place(else_label);
+                } else {
+                    self.jump(end_label);
+                    self.place(else_label);
+                    self.block(otherwise)?;
+                }
+
+                self.place(end_label);
+            },
+            Statement::While(condition, body) => {
+                let (top_label, end_label) = (self.label(), self.label());
+
+                self.place(top_label);
+                let condition = self.expr(condition)?;
+                self.emit(Opcode::JumpIfFalse, &[condition, Operand::Label(end_label)], None);
+                self.temps = 0;
+                self.block(body)?;
+                self.jump(top_label);
+                self.place(end_label);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Works out `expr`, returning where its value ends up. Temps are handed
+    // out like a stack, so each result reuses the temps of its operands.
+    fn expr(&mut self, expr: &Expr) -> Result<Operand, CompileError> {
+        let depth = self.temps;
+
+        match expr {
+            Expr::Number(number) => Ok(Operand::Immediate(*number)),
+            Expr::Variable(name, position) => {
+                match self.variables.iter().position(|variable| variable == name) {
+                    Some(index) => Ok(Operand::Variable(index)),
+                    None => Err(CompileError { position: *position, reason: format!("The variable '{}' is used before it is assigned.", name) })
+                }
+            },
+            Expr::Input => {
+                let result = self.temp();
+                self.emit(Opcode::Input, &[], Some(result));
+                Ok(result)
+            },
+            Expr::Negate(inner, position) => {
+                let inner = self.expr(inner)?;
+                self.temps = depth;
+
+                match inner {
+                    Operand::Immediate(value) => value.checked_neg().map(Operand::Immediate).ok_or_else(|| overflow(*position)),
+                    _ => {
+                        let result = self.temp();
+                        self.emit(Opcode::Multiply, &[inner, Operand::Immediate(-1)], Some(result));
+                        Ok(result)
+                    }
+                }
+            },
+            Expr::Binary(op, left, right, position) => {
+                let left = self.expr(left)?;
+                let right = self.expr(right)?;
+                self.temps = depth;
+
+                if let (Operand::Immediate(a), Operand::Immediate(b)) = (left, right) {
+                    return fold(*op, a, b).map(Operand::Immediate).ok_or_else(|| overflow(*position));
+                }
+
+                let result = self.temp();
+
+                match op {
+                    BinaryOp::Add => self.emit(Opcode::Add, &[left, right], Some(result)),
+                    BinaryOp::Multiply => self.emit(Opcode::Multiply, &[left, right], Some(result)),
+                    BinaryOp::Subtract => {
+                        // The right operand may be in the next temp, but it
+                        // is read before it is overwritten.
+                        let negated = self.temp();
+                        self.emit(Opcode::Multiply, &[right, Operand::Immediate(-1)], Some(negated));
+                        self.emit(Opcode::Add, &[left, negated], Some(result));
+                        self.temps -= 1;
+                    },
+                    BinaryOp::Equals => self.emit(Opcode::Equals, &[left, right], Some(result)),
+                    BinaryOp::LessThan => self.emit(Opcode::LessThan, &[left, right], Some(result)),
+                    BinaryOp::GreaterThan => self.emit(Opcode::LessThan, &[right, left], Some(result)),
+                    BinaryOp::NotEquals | BinaryOp::LessOrEqual | BinaryOp::GreaterOrEqual => {
+                        let (opcode, reads) = match op {
+                            BinaryOp::NotEquals => (Opcode::Equals, [left, right]),
+                            BinaryOp::LessOrEqual => (Opcode::LessThan, [right, left]),
+                            _ => (Opcode::LessThan, [left, right])
+                        };
+
+                        // The opposite comparison, then flip it.
+                        self.emit(opcode, &reads, Some(result));
+                        self.emit(Opcode::Equals, &[result, Operand::Immediate(0)], Some(result));
+                    }
+                }
+
+                Ok(result)
+            }
+        }
+    }
+
+    fn finish(self) -> Compiled {
+        let code_len = self.code.len();
+        let temps_start = code_len + self.variables.len();
+
+        let mut memory: Vec<i32> = self.code.iter()
+            .map(|word| match *word {
+                Word::Value(value) => value,
+                Word::Variable(index) => (code_len + index) as i32,
+                Word::Temp(index) => (temps_start + index) as i32,
+                Word::Label(label) => self.labels[label].expect("Every label is placed.") as i32
+            })
+            .collect();
+        memory.resize(temps_start + self.max_temps, 0);
+
+        let variables = self.variables.into_iter().enumerate()
+            .map(|(index, name)| (name, code_len + index))
+            .collect();
+
+        Compiled { memory, code_len, variables }
+    }
+}
+
+fn fold(op: BinaryOp, a: i32, b: i32) -> Option<i32> {
+    match op {
+        BinaryOp::Add => a.checked_add(b),
+        BinaryOp::Subtract => a.checked_sub(b),
+        BinaryOp::Multiply => a.checked_mul(b),
+        BinaryOp::Equals => Some((a == b) as i32),
+        BinaryOp::NotEquals => Some((a != b) as i32),
+        BinaryOp::LessThan => Some((a < b) as i32),
+        BinaryOp::LessOrEqual => Some((a <= b) as i32),
+        BinaryOp::GreaterThan => Some((a > b) as i32),
+        BinaryOp::GreaterOrEqual => Some((a >= b) as i32)
+    }
+}
+
+fn overflow(position: Position) -> CompileError {
+    CompileError { position, reason: "The constant overflows an i32.".to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::compile;
+    use crate::computer::{Computer, Status};
+    use crate::decoder::Decoding;
+    use crate::protection::Protection;
+
+    // Runs the compiled program with its code read-only and its data segment
+    // not executable, returning its outputs.
+    fn run(source: &str, input: Vec<i32>) -> Vec<i32> {
+        let compiled = compile(source).unwrap();
+        let len = compiled.memory.len();
+
+        let mut computer = Computer::with_memory(compiled.memory, input).with_decoding(Decoding::Strict);
+        computer.protect(0..compiled.code_len, Protection::ReadOnly).unwrap();
+        if compiled.code_len < len {
+            computer.protect(compiled.code_len..len, Protection::NoExecute).unwrap();
+        }
+
+        assert_eq!(computer.run(), Ok(Status::Halted));
+        computer.outputs().to_vec()
+    }
+
+    #[test]
+    fn compiles_expressions() {
+        let compiled = compile("x = 2 * 3 + 4; output(x);").unwrap();
+        assert_eq!(compiled.memory, vec![1101, 10, 0, 7, 4, 7, 99, 0]);
+        assert_eq!(compiled.variables, vec![("x".to_string(), 7)]);
+
+        let source = "a = input(); b = input(); output(a - b); output(-(a * b) + 1); output(a - (b - a));";
+        assert_eq!(run(source, vec![7, 3]), vec![4, -20, 11]);
+
+        let comparisons = "a = input(); b = input();
+            output(a == b); output(a != b); output(a < b); output(a <= b); output(a > b); output(a >= b);";
+        assert_eq!(run(comparisons, vec![2, 5]), vec![0, 1, 1, 1, 0, 0]);
+        assert_eq!(run(comparisons, vec![5, 5]), vec![1, 0, 0, 1, 0, 1]);
+    }
+
+    #[test]
+    fn compiles_control_flow() {
+        // The day 5 comparison with 8, and a factorial.
+        let compare = "
+            n = input();
+            if n < 8 { output(999); } else if n == 8 { output(1000); } else { output(1001); }
+        ";
+        assert_eq!(run(compare, vec![7]), vec![999]);
+        assert_eq!(run(compare, vec![8]), vec![1000]);
+        assert_eq!(run(compare, vec![9]), vec![1001]);
+
+        let factorial = "
+            # Outputs n! for each input until it gets a 0.
+            n = input();
+            while n != 0 {
+                result = 1;
+                while n > 1 {
+                    result = result * n;
+                    n = n - 1;
+                }
+                output(result);
+                n = input();
+            }
+        ";
+        assert_eq!(run(factorial, vec![5, 1, 10, 0]), vec![120, 1, 3_628_800]);
+    }
+
+    #[test]
+    fn reports_errors() {
+        let err = |source: &str| compile(source).unwrap_err().to_string();
+
+        assert_eq!(err("x = y + 1;"), "At line 1, column 5: The variable 'y' is used before it is assigned.");
+        assert_eq!(err("x = 1;\nwhile x { x = x - 1; "), "At line 2, column 20: Expected '}' but found the end of the program.");
+        assert_eq!(err("output(1 / 2);"), "At line 1, column 10: Unexpected '/'.");
+        assert_eq!(err("if = 3;"), "At line 1, column 4: Expected a value but found '='.");
+        assert_eq!(err("x = 2147483647 + 1;"), "At line 1, column 16: The constant overflows an i32.");
+    }
+}
@@ -0,0 +1,547 @@
+//! The Intcode machine itself.
+
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::mem;
+use std::ops::Range;
+
+use crate::decoder::{Arithmetic, Decoding, ExecResult, OpcodeWithParamModes};
+use crate::device::{Device, MappedDevice};
+use crate::error::Error;
+use crate::format::{self, Format};
+use crate::image::{Image, Memory};
+use crate::observer::{Control, Observer};
+use crate::protection::{self, Access, Protection, Region};
+use crate::timing::{CostTable, Timing};
+
+/// How many instructions in a row that cost no cycles `run_for_cycles` runs
+/// before giving up on the budget, so that a cost table of zeroes can't make
+/// it loop forever.
+pub const MAX_FREE_STEPS: usize = 10_000;
+
+/// Where a `Computer` has got to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    /// The computer can carry on running.
+    Running,
+    /// The next instruction is waiting for an input value, see `push_input`.
+    AwaitingInput,
+    /// The computer has executed a halt instruction.
+    Halted,
+    /// An observer stopped the computer before the instruction at the
+    /// pointer, which runs when the computer is next stepped.
+    Breakpoint
+}
+
+/// An Intcode computer: memory, an instruction pointer, and queues of input
+/// and output values.
+#[derive(Debug)]
+pub struct Computer {
+    memory: Memory,
+    input: VecDeque<i32>,
+    outputs: Vec<i32>,
+    pointer: usize,
+    status: Status,
+    devices: Vec<MappedDevice>,
+    decoding: Decoding,
+    arithmetic: Arithmetic,
+    observers: Vec<Box<dyn Observer>>,
+    regions: Vec<Region>,
+    costs: CostTable,
+    timing: Timing
+}
+
+impl Computer {
+    /// Creates a computer from a comma-separated program, eg. `1,0,0,0,99`,
+    /// that will read its input from `input`.
+    pub fn new(raw_memory: &str, input: Vec<i32>) -> Result<Self, Error> {
+        let memory = format::parse_as(Format::CommaSeparated, raw_memory.as_bytes())?;
+        Ok(Computer::with_memory(memory, input))
+    }
+
+    /// Creates a computer from a program image in any of the formats in
+    /// `format`, detecting which one it is.
+    pub fn from_image(image: &[u8], input: Vec<i32>) -> Result<Self, Error> {
+        let memory = format::parse(image)?;
+        Ok(Computer::with_memory(memory, input))
+    }
+
+    /// Creates a computer from memory that has already been parsed.
+    pub fn with_memory(memory: Vec<i32>, input: Vec<i32>) -> Self {
+        Computer::with_image(Image::new(memory), input)
+    }
+
+    /// Creates a computer that shares `image` with any others made from it,
+    /// copying only the pages of it that the computer writes to.
+    pub fn with_image(image: Image, input: Vec<i32>) -> Self {
+        Computer {
+            memory: Memory::new(image),
+            input: input.into(),
+            outputs: Vec::new(),
+            pointer: 0,
+            status: Status::Running,
+            devices: Vec::new(),
+            decoding: Decoding::Lenient,
+            arithmetic: Arithmetic::Checked,
+            observers: Vec::new(),
+            regions: Vec::new(),
+            costs: CostTable::default(),
+            timing: Timing::default()
+        }
+    }
+
+    /// Sets how strictly instructions are decoded. Defaults to
+    /// `Decoding::Lenient`.
+    pub fn with_decoding(mut self, decoding: Decoding) -> Self {
+        self.decoding = decoding;
+        self
+    }
+
+    /// Sets what happens when arithmetic overflows. Defaults to
+    /// `Arithmetic::Checked`.
+    pub fn with_arithmetic(mut self, arithmetic: Arithmetic) -> Self {
+        self.arithmetic = arithmetic;
+        self
+    }
+
+    /// Sets how many cycles each instruction costs. Defaults to
+    /// `CostTable::default()`.
+    pub fn with_costs(mut self, costs: CostTable) -> Self {
+        self.costs = costs;
+        self
+    }
+
+    /// Overwrites memory before the computer is run, eg. to set the noun and
+    /// verb of day 2 with `&[(1, noun), (2, verb)]`.
+    pub fn with_patches(mut self, patches: &[(usize, i32)]) -> Result<Self, Error> {
+        for &(address, value) in patches {
+            self.patch(address, value)?;
+        }
+
+        Ok(self)
+    }
+
+    /// Overwrites a single word of memory, bypassing any mapped device.
+    pub fn patch(&mut self, address: usize, value: i32) -> Result<(), Error> {
+        let len = self.memory.len();
+        self.memory.set(address, value).ok_or(Error::InvalidPatch { address, len })
+    }
+
+    /// Maps `device` into `range`, so that every access to an address in
+    /// that range goes to the device instead of memory.
+    pub fn attach(&mut self, range: Range<usize>, device: Box<dyn Device>) -> Result<(), Error> {
+        if range.start >= range.end {
+            return Err(Error::InvalidMapping { range, reason: "The range is empty.".to_string() });
+        }
+
+        if let Some(existing) = self.devices.iter().find(|d| d.range.start < range.end && range.start < d.range.end) {
+            let reason = format!("It overlaps the device at {:?}.", existing.range);
+            return Err(Error::InvalidMapping { range, reason });
+        }
+
+        self.devices.push(MappedDevice { range, device });
+        Ok(())
+    }
+
+    /// Restricts what the program may do with `range`, overriding any
+    /// earlier protection of the same addresses. Breaking the rules stops
+    /// the computer with `Error::Protected`.
+    pub fn protect(&mut self, range: Range<usize>, protection: Protection) -> Result<(), Error> {
+        if range.start >= range.end {
+            return Err(Error::InvalidMapping { range, reason: "The range is empty.".to_string() });
+        }
+
+        self.regions.push(Region { range, protection });
+        Ok(())
+    }
+
+    /// Attaches `observer`, which is then told about everything the computer
+    /// does. See `Observer` for when each of its methods is called.
+    pub fn observe(&mut self, observer: Box<dyn Observer>) {
+        self.observers.push(observer);
+    }
+
+    /// Queues a value for input instructions to read.
+    pub fn push_input(&mut self, value: i32) {
+        self.input.push_back(value);
+
+        if self.status == Status::AwaitingInput {
+            self.status = Status::Running;
+        }
+    }
+
+    /// Puts the computer back how it was when it was created from its image,
+    /// with `input` queued, ready to run again. Every write to memory is
+    /// thrown away, patches included, as are its outputs and timing. Devices,
+    /// observers and protection are kept as they are.
+    pub fn reset(&mut self, input: Vec<i32>) {
+        self.memory.reset();
+        self.input = input.into();
+        self.outputs.clear();
+        self.pointer = 0;
+        self.status = Status::Running;
+        self.timing = Timing::default();
+    }
+
+    /// How many queued values input instructions have yet to read.
+    pub fn pending_input(&self) -> usize {
+        self.input.len()
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    /// Every value output so far, oldest first.
+    pub fn outputs(&self) -> &[i32] {
+        &self.outputs
+    }
+
+    pub fn last_output(&self) -> Option<i32> {
+        self.outputs.last().copied()
+    }
+
+    /// The address of the next instruction to execute.
+    pub fn pointer(&self) -> usize {
+        self.pointer
+    }
+
+    pub fn status(&self) -> Status {
+        self.status
+    }
+
+    /// The instructions executed so far, and the cycles they cost.
+    pub fn timing(&self) -> &Timing {
+        &self.timing
+    }
+
+    /// Runs until the computer halts, returning the last value it output.
+    ///
+    /// Panics if the program fails, or runs out of input.
+    pub fn exec(&mut self) -> Option<i32> {
+        match self.run() {
+            Ok(Status::Halted) => self.last_output(),
+            Ok(status) => panic!("Computer stopped at {} with status {:?}.", self.pointer, status),
+            Err(err) => panic!("{}", err)
+        }
+    }
+
+    /// Runs until the computer halts, as `exec` does, returning the cycles
+    /// spent by this run along with the last value output.
+    ///
+    /// Panics if the program fails, or runs out of input.
+    pub fn exec_with_cycles(&mut self) -> (Option<i32>, u64) {
+        let start = self.timing.cycles;
+        let output = self.exec();
+        (output, self.timing.cycles - start)
+    }
+
+    /// Runs until the computer halts, needs more input or reaches a
+    /// breakpoint.
+    pub fn run(&mut self) -> Result<Status, Error> {
+        loop {
+            match self.step()? {
+                Status::Running => continue,
+                status => return Ok(status)
+            }
+        }
+    }
+
+    /// Runs for at most `budget` instructions, stopping early if the
+    /// computer halts, needs more input or reaches a breakpoint. Returns
+    /// `Status::Running` if the budget ran out first.
+    pub fn run_for(&mut self, budget: usize) -> Result<Status, Error> {
+        for _ in 0..budget {
+            match self.step()? {
+                Status::Running => continue,
+                status => return Ok(status)
+            }
+        }
+
+        Ok(self.status)
+    }
+
+    /// Runs until at least `budget` cycles have been spent, stopping early if
+    /// the computer halts, needs more input or reaches a breakpoint. The last
+    /// instruction run may take it over the budget. Returns `Status::Running`
+    /// if the budget ran out first, or if `MAX_FREE_STEPS` instructions in a
+    /// row cost nothing.
+    pub fn run_for_cycles(&mut self, budget: u64) -> Result<Status, Error> {
+        let until = self.timing.cycles.saturating_add(budget);
+        let mut free_steps = 0;
+
+        while self.timing.cycles < until && free_steps < MAX_FREE_STEPS {
+            let before = self.timing.cycles;
+
+            match self.step()? {
+                Status::Running => {},
+                status => return Ok(status)
+            }
+
+            free_steps = if self.timing.cycles == before { free_steps + 1 } else { 0 };
+        }
+
+        Ok(self.status)
+    }
+
+    /// Executes a single instruction.
+    ///
+    /// An input instruction with no input waiting leaves the pointer where it
+    /// is and returns `Status::AwaitingInput`, so that it is executed again
+    /// on the next step.
+    pub fn step(&mut self) -> Result<Status, Error> {
+        if self.status == Status::Halted {
+            return Ok(Status::Halted);
+        }
+
+        let pointer = self.pointer;
+        let resuming = self.status == Status::Breakpoint;
+        let opcode_with_param_modes = OpcodeWithParamModes::decode(self.fetch(pointer)?, self.decoding)
+            .map_err(|reason| Error::InvalidInstruction { pointer, reason })?;
+
+        let mut observers = mem::take(&mut self.observers);
+        let control = observers.iter_mut().fold(Control::Continue, |control, observer| {
+            match observer.before(self, &opcode_with_param_modes) {
+                Control::Break => Control::Break,
+                Control::Continue => control
+            }
+        });
+        self.observers = observers;
+
+        if control == Control::Break && !resuming {
+            self.status = Status::Breakpoint;
+            return Ok(self.status);
+        }
+
+        self.status = match opcode_with_param_modes.exec(self, pointer) {
+            ExecResult::Success(next_pointer) => {
+                self.pointer = next_pointer;
+                Status::Running
+            },
+            ExecResult::AwaitingInput => Status::AwaitingInput,
+            ExecResult::Halt => Status::Halted,
+            ExecResult::Failed(err) => return Err(err)
+        };
+
+        if self.status != Status::AwaitingInput {
+            let cycles = self.costs.cost(&opcode_with_param_modes);
+            self.timing.record(opcode_with_param_modes.opcode, cycles);
+        }
+
+        let mut observers = mem::take(&mut self.observers);
+        for observer in observers.iter_mut() {
+            match self.status {
+                Status::Running => observer.after(self, pointer, &opcode_with_param_modes),
+                Status::Halted => observer.halt(self),
+                _ => {}
+            }
+        }
+        self.observers = observers;
+
+        if self.status == Status::Running {
+            for mapped in self.devices.iter_mut() {
+                mapped.device.tick();
+            }
+        }
+
+        Ok(self.status)
+    }
+
+    pub(crate) fn arithmetic(&self) -> Arithmetic {
+        self.arithmetic
+    }
+
+    pub(crate) fn address(&self, raw: i32) -> Result<usize, Error> {
+        usize::try_from(raw).map_err(|_| Error::InvalidAddress {
+            pointer: self.pointer,
+            address: raw as i64
+        })
+    }
+
+    fn load(&mut self, address: usize) -> Result<i32, Error> {
+        match self.devices.iter_mut().find(|d| d.range.contains(&address)) {
+            Some(mapped) => Ok(mapped.device.read(address - mapped.range.start)),
+            None => self.memory.get(address).copied().ok_or(Error::InvalidAddress {
+                pointer: self.pointer,
+                address: address as i64
+            })
+        }
+    }
+
+    // Loads an instruction word, or one of its raw params, telling any
+    // observers.
+    pub(crate) fn fetch(&mut self, address: usize) -> Result<i32, Error> {
+        protection::check(&self.regions, self.pointer, address, Access::Execute)?;
+        let value = self.load(address)?;
+
+        for observer in self.observers.iter_mut() {
+            observer.fetch(address, value);
+        }
+
+        Ok(value)
+    }
+
+    // Loads the value of a position mode param, telling any observers.
+    pub(crate) fn load_param(&mut self, address: usize) -> Result<i32, Error> {
+        protection::check(&self.regions, self.pointer, address, Access::Read)?;
+        let value = self.load(address)?;
+
+        for observer in self.observers.iter_mut() {
+            observer.read(address, value);
+        }
+
+        Ok(value)
+    }
+
+    pub(crate) fn store(&mut self, address: usize, value: i32) -> Result<(), Error> {
+        let pointer = self.pointer;
+        protection::check(&self.regions, pointer, address, Access::Write)?;
+
+        match self.devices.iter_mut().find(|d| d.range.contains(&address)) {
+            Some(mapped) => mapped.device.write(address - mapped.range.start, value),
+            None => self.memory.set(address, value).ok_or(Error::InvalidAddress { pointer, address: address as i64 })?
+        }
+
+        for observer in self.observers.iter_mut() {
+            observer.write(address, value);
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn read(&mut self) -> Option<i32> {
+        let value = self.input.pop_front()?;
+
+        for observer in self.observers.iter_mut() {
+            observer.input(value);
+        }
+
+        Some(value)
+    }
+
+    pub(crate) fn write(&mut self, value: i32) {
+        for observer in self.observers.iter_mut() {
+            observer.output(value);
+        }
+
+        self.outputs.push(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Computer, Status};
+    use crate::decoder::{Arithmetic, Decoding, Opcode};
+    use crate::error::Error;
+    use crate::format::{self, Format};
+
+    const LARGE_EXAMPLE: &str = "3,21,1008,21,8,20,1005,20,22,107,8,21,20,1006,20,31,1106,0,36,98,0,0,1002,21,125,20,4,20,1105,1,46,104,999,1105,1,46,1101,1000,1,20,4,20,1105,1,46,98,99";
+
+    #[test]
+    fn loads_any_image_format() {
+        for format in &[Format::LinePerWord, Format::Binary, Format::Annotated] {
+            let image = format::write(*format, &format::parse(LARGE_EXAMPLE.as_bytes()).unwrap());
+            assert_eq!(Computer::from_image(&image, vec![9]).unwrap().exec(), Some(1001));
+        }
+
+        assert_eq!(
+            Computer::new("3,0,4,0,x9", vec![1]).unwrap_err().to_string(),
+            "Unexpected 'x9' at line 1, column 9: Failed to parse memory as i32: invalid digit found in string"
+        );
+    }
+
+    #[test]
+    fn patches_memory_before_running() {
+        let mut computer = Computer::new("1,0,0,0,99", vec![]).unwrap()
+            .with_patches(&[(1, 4), (2, 4)])
+            .unwrap();
+
+        assert_eq!(computer.run(), Ok(Status::Halted));
+        assert_eq!(computer.memory().to_vec(), vec![198, 4, 4, 0, 99]);
+
+        assert_eq!(
+            Computer::new("99", vec![]).unwrap().with_patches(&[(1, 0)]).unwrap_err(),
+            Error::InvalidPatch { address: 1, len: 1 }
+        );
+    }
+
+    #[test]
+    fn waits_for_input() {
+        let mut computer = Computer::new("3,11,3,12,1,11,12,13,99,0,0,0,0,0", vec![2]).unwrap();
+
+        assert_eq!(computer.run(), Ok(Status::AwaitingInput));
+        assert_eq!(computer.pointer(), 2);
+
+        computer.push_input(3);
+        assert_eq!(computer.status(), Status::Running);
+        assert_eq!(computer.step(), Ok(Status::Running));
+        assert_eq!(computer.step(), Ok(Status::Running));
+        assert_eq!(computer.memory()[13], 5);
+    }
+
+    #[test]
+    fn runs_for_a_budget() {
+        let mut computer = Computer::new("104,1,104,2,104,3,99", vec![]).unwrap();
+
+        assert_eq!(computer.run_for(2), Ok(Status::Running));
+        assert_eq!(computer.outputs(), &[1, 2]);
+        assert_eq!(computer.run_for(0), Ok(Status::Running));
+        assert_eq!(computer.run_for(5), Ok(Status::Halted));
+        assert_eq!(computer.outputs(), &[1, 2, 3]);
+    }
+
+    #[test]
+    fn reports_invalid_addresses() {
+        assert_eq!(
+            Computer::new("1,0,0,-4,99", vec![]).unwrap().run(),
+            Err(Error::InvalidAddress { pointer: 0, address: -4 })
+        );
+        assert_eq!(
+            Computer::new("4,20,99", vec![]).unwrap().run(),
+            Err(Error::InvalidAddress { pointer: 0, address: 20 })
+        );
+        assert_eq!(
+            Computer::new("1105,1,7,99", vec![]).unwrap().run(),
+            Err(Error::InvalidAddress { pointer: 7, address: 7 })
+        );
+    }
+
+    #[test]
+    fn applies_the_arithmetic_policy() {
+        // Adds 1 to the max i32, then multiplies the result by 2.
+        let program = "1101,2147483647,1,13,1002,13,2,14,4,13,4,14,99,0,0";
+        let run = |arithmetic| {
+            let mut computer = Computer::new(program, vec![]).unwrap().with_arithmetic(arithmetic);
+            computer.run().map(|_| computer.outputs().to_vec())
+        };
+
+        assert_eq!(run(Arithmetic::Checked), Err(Error::Overflow {
+            pointer: 0,
+            opcode: Opcode::Add,
+            a: i32::MAX,
+            b: 1
+        }));
+        assert_eq!(run(Arithmetic::Wrapping), Ok(vec![i32::MIN, 0]));
+        assert_eq!(run(Arithmetic::Saturating), Ok(vec![i32::MAX, i32::MAX]));
+
+        assert_eq!(
+            Computer::new("1102,65536,32768,5,99,0", vec![]).unwrap().run(),
+            Err(Error::Overflow { pointer: 0, opcode: Opcode::Multiply, a: 65536, b: 32768 })
+        );
+    }
+
+    #[test]
+    fn lenient_computers_ignore_write_modes() {
+        assert_eq!(Computer::new("11002,4,3,4,33", vec![]).unwrap().exec(), None);
+    }
+
+    #[test]
+    fn strict_computers_reject_write_modes() {
+        let mut computer = Computer::new("11002,4,3,4,33", vec![]).unwrap().with_decoding(Decoding::Strict);
+
+        assert_eq!(computer.run(), Err(Error::InvalidInstruction {
+            pointer: 0,
+            reason: "Param 3 of instruction '11002' is written to so must be in position mode, not '1'.".to_string()
+        }));
+    }
+}
@@ -0,0 +1,221 @@
+//! Measuring which parts of a program, and of the interpreter, runs used.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::rc::Rc;
+
+use crate::computer::Computer;
+use crate::decoder::{Opcode, OpcodeWithParamModes, ParamMode};
+use crate::observer::{Control, Observer};
+
+const OPCODES: [Opcode; 9] = [
+    Opcode::Add,
+    Opcode::Multiply,
+    Opcode::Input,
+    Opcode::Output,
+    Opcode::JumpIfTrue,
+    Opcode::JumpIfFalse,
+    Opcode::LessThan,
+    Opcode::Equals,
+    Opcode::Halt
+];
+
+/// An opcode along with the modes of the params it reads. Params that are
+/// written to are left out, as they are always addresses.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Signature {
+    pub opcode: Opcode,
+    pub modes: Vec<ParamMode>
+}
+
+impl Signature {
+    pub fn of(instruction: &OpcodeWithParamModes) -> Self {
+        let writable = instruction.opcode.writable_params();
+        let modes = (0..instruction.opcode.num_params())
+            .filter(|i| !writable.contains(&(i + 1)))
+            .map(|i| instruction.param_modes[i])
+            .collect();
+
+        Signature { opcode: instruction.opcode, modes }
+    }
+
+    /// Every signature the interpreter can execute.
+    pub fn all() -> Vec<Self> {
+        let mut all = Vec::new();
+
+        for &opcode in &OPCODES {
+            let reads = opcode.num_params() - opcode.writable_params().len();
+
+            for combination in 0..(1 << reads) {
+                let modes = (0..reads)
+                    .map(|i| if combination & (1 << i) == 0 { ParamMode::PositionMode } else { ParamMode::ImmediateMode })
+                    .collect();
+                all.push(Signature { opcode, modes });
+            }
+        }
+
+        all
+    }
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let modes: Vec<&str> = self.modes.iter()
+            .map(|mode| match mode {
+                ParamMode::PositionMode => "position",
+                ParamMode::ImmediateMode => "immediate"
+            })
+            .collect();
+
+        write!(f, "{}({})", self.opcode.mnemonic(), modes.join(", "))
+    }
+}
+
+/// Which ways a conditional jump has gone.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Branch {
+    pub taken: bool,
+    pub not_taken: bool
+}
+
+/// What one or more runs covered: the address of every instruction that ran,
+/// which ways each jump went and every signature that was executed.
+///
+/// Attach it to a `Computer` as an `Observer`, or use `measure`. A jump
+/// whose target is the next instruction counts as not taken.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Coverage {
+    pub executed: BTreeSet<usize>,
+    pub branches: BTreeMap<usize, Branch>,
+    pub signatures: BTreeSet<Signature>
+}
+
+impl Coverage {
+    /// Runs `memory` once with each of `inputs`, for at most `budget`
+    /// instructions each, returning what the runs covered between them. Runs
+    /// that fail count up to where they failed, and runs that use up their
+    /// budget up to where they got to.
+    pub fn measure(memory: &[i32], inputs: &[Vec<i32>], budget: usize) -> Self {
+        let coverage = Rc::new(RefCell::new(Coverage::default()));
+
+        for input in inputs {
+            let mut computer = Computer::with_memory(memory.to_vec(), input.clone());
+            computer.observe(Box::new(coverage.clone()));
+            let _ = computer.run_for(budget);
+        }
+
+        let coverage = coverage.borrow().clone();
+        coverage
+    }
+
+    /// Adds what `other` covered to this.
+    pub fn merge(&mut self, other: &Coverage) {
+        self.executed.extend(&other.executed);
+        self.signatures.extend(other.signatures.iter().cloned());
+
+        for (&address, branch) in &other.branches {
+            let merged = self.branches.entry(address).or_default();
+            merged.taken |= branch.taken;
+            merged.not_taken |= branch.not_taken;
+        }
+    }
+
+    /// Every signature that has not been executed, in opcode order.
+    pub fn missing_signatures(&self) -> Vec<Signature> {
+        Signature::all().into_iter()
+            .filter(|signature| !self.signatures.contains(signature))
+            .collect()
+    }
+}
+
+impl Observer for Coverage {
+    fn before(&mut self, computer: &Computer, instruction: &OpcodeWithParamModes) -> Control {
+        self.executed.insert(computer.pointer());
+        self.signatures.insert(Signature::of(instruction));
+        Control::Continue
+    }
+
+    fn after(&mut self, computer: &Computer, pointer: usize, instruction: &OpcodeWithParamModes) {
+        if let Opcode::JumpIfTrue | Opcode::JumpIfFalse = instruction.opcode {
+            let branch = self.branches.entry(pointer).or_default();
+
+            if computer.pointer() == pointer + instruction.opcode.num_params() + 1 {
+                branch.not_taken = true;
+            } else {
+                branch.taken = true;
+            }
+        }
+    }
+}
+
+impl fmt::Display for Coverage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let executed: Vec<String> = self.executed.iter().map(|address| address.to_string()).collect();
+        writeln!(f, "Ran {} instructions, at: {}", executed.len(), executed.join(", "))?;
+
+        writeln!(f, "Branches:")?;
+        for (address, branch) in &self.branches {
+            let ways = match (branch.taken, branch.not_taken) {
+                (true, true) => "both ways",
+                (true, false) => "always taken",
+                _ => "never taken"
+            };
+            writeln!(f, "  {}: {}", address, ways)?;
+        }
+
+        let missing = self.missing_signatures();
+        writeln!(f, "Executed {} of {} signatures, missing:", self.signatures.len(), Signature::all().len())?;
+        for signature in missing {
+            writeln!(f, "  {}", signature)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Branch, Coverage, Signature};
+    use crate::decoder::{Opcode, ParamMode};
+
+    // Outputs 0 if the input was 0, or 1 otherwise.
+    const JUMP: [i32; 16] = [3, 12, 6, 12, 15, 1, 13, 14, 13, 4, 13, 99, -1, 0, 1, 9];
+
+    #[test]
+    fn measures_addresses_and_branches() {
+        let zero = Coverage::measure(&JUMP, &[vec![0]], 100);
+        assert_eq!(zero.executed.iter().copied().collect::<Vec<_>>(), vec![0, 2, 9, 11]);
+        assert_eq!(zero.branches[&2], Branch { taken: true, not_taken: false });
+
+        let mut both = Coverage::measure(&JUMP, &[vec![1]], 100);
+        both.merge(&zero);
+        assert_eq!(both, Coverage::measure(&JUMP, &[vec![0], vec![1]], 100));
+        assert_eq!(both.executed.iter().copied().collect::<Vec<_>>(), vec![0, 2, 5, 9, 11]);
+        assert_eq!(both.branches[&2], Branch { taken: true, not_taken: true });
+
+        // Loops forever on a 0, so only the budget stops it.
+        let looping = Coverage::measure(&[3, 7, 1006, 7, 2, 4, 7, -1], &[vec![0]], 100);
+        assert_eq!(looping.executed.iter().copied().collect::<Vec<_>>(), vec![0, 2]);
+        assert_eq!(looping.branches[&2], Branch { taken: true, not_taken: false });
+    }
+
+    #[test]
+    fn reports_missing_signatures() {
+        let coverage = Coverage::measure(&JUMP, &[vec![0], vec![1]], 100);
+
+        assert_eq!(Signature::all().len(), 28);
+        assert!(coverage.signatures.contains(&Signature {
+            opcode: Opcode::JumpIfFalse,
+            modes: vec![ParamMode::PositionMode, ParamMode::PositionMode]
+        }));
+        assert_eq!(coverage.missing_signatures().len(), 23);
+        assert_eq!(coverage.to_string().lines().take(4).collect::<Vec<_>>(), vec![
+            "Ran 5 instructions, at: 0, 2, 5, 9, 11",
+            "Branches:",
+            "  2: both ways",
+            "Executed 5 of 28 signatures, missing:"
+        ]);
+        assert!(coverage.to_string().contains("\n  add(position, immediate)\n"));
+    }
+}
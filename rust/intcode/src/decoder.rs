@@ -0,0 +1,545 @@
+//! Decoding of instruction words into opcodes and param modes.
+
+use std::collections::HashSet;
+use std::convert::TryFrom;
+
+use crate::computer::Computer;
+use crate::error::Error;
+
+/// A decoded instruction word: the opcode along with the mode of each of its
+/// params.
+///
+/// There are always three param modes, whatever the opcode. Params that are
+/// written to are always decoded as `ImmediateMode`, as the raw param is the
+/// address that is written to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpcodeWithParamModes {
+    pub opcode: Opcode,
+    pub param_modes: Vec<ParamMode>
+}
+
+/// How strictly instruction words are checked when they are decoded.
+///
+/// `Lenient` matches what the interpreter has always accepted: any mode digit
+/// on a param that is written to is ignored (it is always treated as an
+/// address), as are any digits above the modes of the third param. `Strict`
+/// rejects all of those, along with negative instruction words.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Decoding {
+    Lenient,
+    Strict
+}
+
+/// What happens when `Add` or `Multiply` overflows an `i32`.
+///
+/// The policy is applied explicitly, so a program behaves the same in debug
+/// and release builds whichever is chosen.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arithmetic {
+    /// Overflow stops the computer with `Error::Overflow`.
+    Checked,
+    /// Results wrap around in two's complement.
+    Wrapping,
+    /// Results are clamped to `i32::MIN` or `i32::MAX`.
+    Saturating
+}
+
+impl Arithmetic {
+    /// Adds `a` and `b`, returning `None` if they overflow under `Checked`.
+    pub fn add(self, a: i32, b: i32) -> Option<i32> {
+        match self {
+            Arithmetic::Checked => a.checked_add(b),
+            Arithmetic::Wrapping => Some(a.wrapping_add(b)),
+            Arithmetic::Saturating => Some(a.saturating_add(b))
+        }
+    }
+
+    /// Multiplies `a` and `b`, returning `None` if they overflow under
+    /// `Checked`.
+    pub fn multiply(self, a: i32, b: i32) -> Option<i32> {
+        match self {
+            Arithmetic::Checked => a.checked_mul(b),
+            Arithmetic::Wrapping => Some(a.wrapping_mul(b)),
+            Arithmetic::Saturating => Some(a.saturating_mul(b))
+        }
+    }
+}
+
+impl TryFrom<i32> for OpcodeWithParamModes {
+    type Error = String;
+
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        OpcodeWithParamModes::decode(value, Decoding::Lenient)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum ExecResult<E> {
+    Success(usize),
+    AwaitingInput,
+    Halt,
+    Failed(E),
+}
+
+impl OpcodeWithParamModes {
+    /// Decodes an instruction word, checking it as strictly as `decoding` asks.
+    pub fn decode(value: i32, decoding: Decoding) -> Result<Self, String> {
+        if decoding == Decoding::Strict && value < 0 {
+            return Err(format!("Negative instruction '{}' encountered.", value));
+        }
+
+        if decoding == Decoding::Strict && value >= 100_000 {
+            return Err(format!("Instruction '{}' has more than 5 digits.", value));
+        }
+
+        let opcode = Opcode::try_from(value % 100)?;
+        let num_params = opcode.num_params();
+        let writable_params = opcode.writable_params();
+        let mut param_modes = Vec::with_capacity(3);
+
+        for (i, divisor) in [100, 1000, 10000].iter().enumerate() {
+            let digit = (value / divisor) % 10;
+
+            // Param indices are 1 based.
+            let param_mode = if writable_params.contains(&(i + 1)) {
+                if decoding == Decoding::Strict && digit != 0 {
+                    return Err(format!(
+                        "Param {} of instruction '{}' is written to so must be in position mode, not '{}'.",
+                        i + 1, value, digit
+                    ));
+                }
+
+                ParamMode::ImmediateMode
+            } else {
+                if decoding == Decoding::Strict && i >= num_params && digit != 0 {
+                    return Err(format!(
+                        "Instruction '{}' sets mode '{}' for param {} but {:?} only takes {}.",
+                        value, digit, i + 1, opcode, num_params
+                    ));
+                }
+
+                ParamMode::try_from(digit)?
+            };
+
+            param_modes.push(param_mode);
+        }
+
+        Ok(OpcodeWithParamModes {
+            opcode,
+            param_modes
+        })
+    }
+
+    pub(crate) fn exec(&self, computer: &mut Computer, opcode_pos: usize) -> ExecResult<Error> {
+        let params: Params = match self.extract_params(computer, opcode_pos) {
+            Ok(params) => params,
+            Err(err) => return ExecResult::Failed(err)
+        };
+
+        let next_pointer = opcode_pos + self.opcode.num_params() + 1;
+        let res = self.opcode.exec(params, computer.arithmetic());
+
+        let outcome = match res {
+            OpcodeResult::NoOp => Ok(ExecResult::Success(next_pointer)),
+            OpcodeResult::WriteToOutput(value) => {
+                computer.write(value);
+                Ok(ExecResult::Success(next_pointer))
+            },
+            OpcodeResult::WriteValueToMemory(value, to) => {
+                computer.address(to)
+                    .and_then(|to| computer.store(to, value))
+                    .map(|_| ExecResult::Success(next_pointer))
+            },
+            OpcodeResult::WriteInputToMemory(to) => {
+                match computer.read() {
+                    Some(value) => {
+                        computer.address(to)
+                            .and_then(|to| computer.store(to, value))
+                            .map(|_| ExecResult::Success(next_pointer))
+                    },
+                    None => Ok(ExecResult::AwaitingInput)
+                }
+            },
+            OpcodeResult::JumpTo(to) => computer.address(to).map(ExecResult::Success),
+            OpcodeResult::Halt => Ok(ExecResult::Halt),
+            OpcodeResult::Overflow(a, b) => Err(Error::Overflow {
+                pointer: opcode_pos,
+                opcode: self.opcode,
+                a,
+                b
+            }),
+            OpcodeResult::Failed(err) => Err(Error::InvalidInstruction {
+                pointer: opcode_pos,
+                reason: err
+            })
+        };
+
+        outcome.unwrap_or_else(ExecResult::Failed)
+    }
+
+    pub(crate) fn extract_params(&self, computer: &mut Computer, opcode_pos: usize) -> Result<Params, Error> {
+        let num_params = self.opcode.num_params();
+
+        let mut params = Vec::with_capacity(3);
+        params.resize(3, None);
+
+        let raws = (0..num_params)
+            .map(|i| computer.fetch(opcode_pos + i + 1))
+            .collect::<Result<Vec<i32>, Error>>()?;
+
+        for (i, raw) in raws.into_iter().enumerate() {
+            params[i] = match self.param_modes[i] {
+                ParamMode::PositionMode => {
+                    let address = computer.address(raw)?;
+                    Some(computer.load_param(address)?)
+                },
+                ParamMode::ImmediateMode => Some(raw)
+            };
+        }
+
+        Params::try_from(params).map_err(|reason| Error::InvalidInstruction {
+            pointer: opcode_pos,
+            reason
+        })
+    }
+
+    /// Renders the instruction at `opcode_pos` as assembly, eg. `mul [4], 3, [4]`.
+    /// Params in position mode, and params that are written to, are shown as
+    /// `[address]`.
+    pub fn disassemble(&self, memory: &[i32], opcode_pos: usize) -> String {
+        let writable_params = self.opcode.writable_params();
+
+        let params: Vec<String> = (0..self.opcode.num_params())
+            .map(|i| {
+                let raw = match memory.get(opcode_pos + i + 1) {
+                    Some(raw) => raw.to_string(),
+                    None => "?".to_string()
+                };
+
+                match self.param_modes[i] {
+                    ParamMode::ImmediateMode if !writable_params.contains(&(i + 1)) => raw,
+                    _ => format!("[{}]", raw)
+                }
+            })
+            .collect();
+
+        if params.is_empty() {
+            self.opcode.mnemonic().to_string()
+        } else {
+            format!("{} {}", self.opcode.mnemonic(), params.join(", "))
+        }
+    }
+}
+
+/// How a param is turned into the value an instruction operates on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ParamMode {
+    /// The param is the address of the value.
+    PositionMode,
+    /// The param is the value itself.
+    ImmediateMode,
+}
+
+impl TryFrom<i32> for ParamMode {
+    type Error = String;
+
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(ParamMode::PositionMode),
+            1 => Ok(ParamMode::ImmediateMode),
+            code => Err(format!("Unknown param mode '{}' encountered.", code))
+        }
+    }
+}
+
+pub(crate) struct Params {
+    first: Option<i32>,
+    second: Option<i32>,
+    third: Option<i32>,
+}
+
+impl TryFrom<Vec<Option<i32>>> for Params {
+    type Error = String;
+
+    fn try_from(value: Vec<Option<i32>>) -> Result<Self, Self::Error> {
+        if value.len() != 3 {
+            return Err("Params can only be constructed from a Vec of exactly 3 in length.".to_string());
+        }
+
+        Ok(Params {
+            first: value[0],
+            second: value[1],
+            third: value[2]
+        })
+    }
+}
+
+/// Every operation the interpreter understands, numbered by its opcode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Opcode {
+    Add = 1,
+    Multiply = 2,
+    Input = 3,
+    Output = 4,
+    JumpIfTrue = 5,
+    JumpIfFalse = 6,
+    LessThan = 7,
+    Equals = 8,
+    Halt = 99
+}
+
+impl TryFrom<i32> for Opcode {
+    type Error = String;
+
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(Opcode::Add),
+            2 => Ok(Opcode::Multiply),
+            3 => Ok(Opcode::Input),
+            4 => Ok(Opcode::Output),
+            5 => Ok(Opcode::JumpIfTrue),
+            6 => Ok(Opcode::JumpIfFalse),
+            7 => Ok(Opcode::LessThan),
+            8 => Ok(Opcode::Equals),
+            99 => Ok(Opcode::Halt),
+            code => Err(format!("Unknown opcode '{}' encountered.", code))
+        }
+    }
+}
+
+impl Opcode {
+    /// The short name used for the opcode in disassembly.
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Opcode::Add => "add",
+            Opcode::Multiply => "mul",
+            Opcode::Input => "in",
+            Opcode::Output => "out",
+            Opcode::JumpIfTrue => "jt",
+            Opcode::JumpIfFalse => "jf",
+            Opcode::LessThan => "lt",
+            Opcode::Equals => "eq",
+            Opcode::Halt => "hlt",
+        }
+    }
+
+    pub fn num_params(&self) -> usize {
+        match self {
+            Opcode::Add => 3,
+            Opcode::Multiply => 3,
+            Opcode::Input => 1,
+            Opcode::Output => 1,
+            Opcode::JumpIfTrue => 2,
+            Opcode::JumpIfFalse => 2,
+            Opcode::LessThan => 3,
+            Opcode::Equals => 3,
+            Opcode::Halt => 0,
+        }
+    }
+
+    /// The (1 based) indices of the params this opcode writes to.
+    pub fn writable_params(&self) -> HashSet<usize> {
+        let mut params = HashSet::new();
+
+        // Param indices are 1 based.
+        match self {
+            Opcode::Add => params.insert(3),
+            Opcode::Multiply => params.insert(3),
+            Opcode::Input => params.insert(1),
+            Opcode::Output => false,
+            Opcode::JumpIfTrue => false,
+            Opcode::JumpIfFalse => false,
+            Opcode::LessThan => params.insert(3),
+            Opcode::Equals => params.insert(3),
+            Opcode::Halt => false,
+        };
+
+        params
+    }
+
+    fn exec(&self, params: Params, arithmetic: Arithmetic) -> OpcodeResult<String> {
+        match self {
+            Opcode::Add => {
+                match (params.first, params.second, params.third) {
+                    (Some(a), Some(b), Some(res)) => {
+                        match arithmetic.add(a, b) {
+                            Some(value) => OpcodeResult::WriteValueToMemory(value, res),
+                            None => OpcodeResult::Overflow(a, b)
+                        }
+                    },
+                    _ => OpcodeResult::Failed("Add expected 3 params.".to_string())
+                }
+            },
+            Opcode::Multiply => {
+                match (params.first, params.second, params.third) {
+                    (Some(a), Some(b), Some(res)) => {
+                        match arithmetic.multiply(a, b) {
+                            Some(value) => OpcodeResult::WriteValueToMemory(value, res),
+                            None => OpcodeResult::Overflow(a, b)
+                        }
+                    },
+                    _ => OpcodeResult::Failed("Multiply expected 3 params.".to_string())
+                }
+            },
+            Opcode::Input => {
+                match params.first {
+                    Some(res) => {
+                        OpcodeResult::WriteInputToMemory(res)
+                    },
+                    _ => OpcodeResult::Failed("Input expected 1 param.".to_string())
+                }
+            },
+            Opcode::Output => {
+                match params.first {
+                    Some(value) => {
+                        OpcodeResult::WriteToOutput(value)
+                    },
+                    _ => OpcodeResult::Failed("Output expected 1 param.".to_string())
+                }
+            },
+            Opcode::JumpIfTrue => {
+                match (params.first, params.second) {
+                    (Some(value), Some(jump_to)) if value != 0 => {
+                        OpcodeResult::JumpTo(jump_to)
+                    },
+                    (Some(_), Some(_)) => {
+                        OpcodeResult::NoOp
+                    },
+                    _ => OpcodeResult::Failed("JumpIfTrue expected 2 params.".to_string())
+                }
+            },
+            Opcode::JumpIfFalse => {
+                match (params.first, params.second) {
+                    (Some(0), Some(jump_to)) => {
+                        OpcodeResult::JumpTo(jump_to)
+                    },
+                    (Some(_), Some(_)) => {
+                        OpcodeResult::NoOp
+                    },
+                    _ => OpcodeResult::Failed("JumpIfFalse expected 2 params.".to_string())
+                }
+            },
+            Opcode::LessThan => {
+                match (params.first, params.second, params.third) {
+                    (Some(a), Some(b), Some(res)) => {
+                        OpcodeResult::WriteValueToMemory((a < b) as i32, res)
+                    },
+                    _ => OpcodeResult::Failed("LessThan expected 3 params.".to_string())
+                }
+            },
+            Opcode::Equals => {
+                match (params.first, params.second, params.third) {
+                    (Some(a), Some(b), Some(res)) => {
+                        OpcodeResult::WriteValueToMemory((a == b) as i32, res)
+                    },
+                    _ => OpcodeResult::Failed("Equals expected 3 params.".to_string())
+                }
+            },
+            Opcode::Halt => OpcodeResult::Halt
+        }
+    }
+}
+
+// Addresses are left as they were read from memory, and checked by the
+// computer when they are used.
+#[derive(Debug)]
+enum OpcodeResult<E> {
+    NoOp,
+    WriteToOutput(i32),
+    WriteValueToMemory(i32, i32),
+    WriteInputToMemory(i32),
+    JumpTo(i32),
+    Halt,
+    Overflow(i32, i32),
+    Failed(E)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Decoding, OpcodeWithParamModes, ParamMode};
+
+    // Each opcode with the role of each of its params: `r` is read from, `w`
+    // is written to and `-` is unused.
+    const SIGNATURES: &[(i32, &str)] = &[
+        (1, "rrw"),
+        (2, "rrw"),
+        (3, "w--"),
+        (4, "r--"),
+        (5, "rr-"),
+        (6, "rr-"),
+        (7, "rrw"),
+        (8, "rrw"),
+        (99, "---")
+    ];
+
+    #[test]
+    fn decodes_every_opcode_and_mode_combination() {
+        for &(opcode, signature) in SIGNATURES {
+            let roles: Vec<char> = signature.chars().collect();
+
+            for modes in 0..27 {
+                let digits = [modes % 3, (modes / 3) % 3, modes / 9];
+                let word = opcode + digits[0] * 100 + digits[1] * 1000 + digits[2] * 10000;
+
+                let lenient = (0..3).all(|i| roles[i] == 'w' || digits[i] < 2);
+                let strict = (0..3).all(|i| if roles[i] == 'r' { digits[i] < 2 } else { digits[i] == 0 });
+
+                // Params that are written to always hold the raw address.
+                let expected_modes: Vec<ParamMode> = (0..3)
+                    .map(|i| if roles[i] == 'w' || digits[i] == 1 { ParamMode::ImmediateMode } else { ParamMode::PositionMode })
+                    .collect();
+
+                for &(decoding, valid) in &[(Decoding::Lenient, lenient), (Decoding::Strict, strict)] {
+                    match OpcodeWithParamModes::decode(word, decoding) {
+                        Ok(decoded) => {
+                            assert!(valid, "{} should not decode in {:?} mode", word, decoding);
+                            assert_eq!(decoded.param_modes, expected_modes, "{} in {:?} mode", word, decoding);
+                        },
+                        Err(err) => assert!(!valid, "{} should decode in {:?} mode: {}", word, decoding, err)
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn strict_decoding_rejects_malformed_words() {
+        // The word, then whether it decodes in lenient and strict mode.
+        let cases = [
+            (1002, true, true),
+            (11002, true, false),
+            (10004, true, false),
+            (1099, true, false),
+            (101108, true, false),
+            (1_000_001, true, false),
+            (-1, false, false),
+            (-1101, false, false),
+            (2, true, true)
+        ];
+
+        for &(word, lenient, strict) in &cases {
+            assert_eq!(OpcodeWithParamModes::decode(word, Decoding::Lenient).is_ok(), lenient, "{} in lenient mode", word);
+            assert_eq!(OpcodeWithParamModes::decode(word, Decoding::Strict).is_ok(), strict, "{} in strict mode", word);
+        }
+
+        assert_eq!(
+            OpcodeWithParamModes::decode(-1101, Decoding::Strict).unwrap_err(),
+            "Negative instruction '-1101' encountered."
+        );
+        assert_eq!(
+            OpcodeWithParamModes::decode(11101, Decoding::Strict).unwrap_err(),
+            "Param 3 of instruction '11101' is written to so must be in position mode, not '1'."
+        );
+    }
+
+    #[test]
+    fn disassembles_instructions() {
+        let memory = [1002, 4, 3, 4, 33, 99, 4];
+        let decode = |pos: usize| OpcodeWithParamModes::decode(memory[pos], Decoding::Lenient).unwrap();
+
+        assert_eq!(decode(0).disassemble(&memory, 0), "mul [4], 3, [4]");
+        assert_eq!(decode(5).disassemble(&memory, 5), "hlt");
+        assert_eq!(decode(3).disassemble(&memory, 3), "out [33]");
+        assert_eq!(decode(6).disassemble(&memory, 6), "out [?]");
+    }
+}
@@ -0,0 +1,590 @@
+//! Turning a program back into structured pseudo-code.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::convert::TryFrom;
+
+use crate::decoder::{Opcode, OpcodeWithParamModes, ParamMode};
+
+/// Recovers structured pseudo-code from a memory image.
+///
+/// Code is found by following control flow from the entry points, so data
+/// is never decoded as instructions. Jumps whose target is read from memory
+/// are resolved when the target cell was set to a constant earlier in the
+/// same straight-line run of code, with no jump into the run in between, and
+/// as long as every other jump can be resolved too. The jumps are then folded
+/// back into `while`, `loop`, `do`/`while` and `if`/`else` blocks where they
+/// nest cleanly, and into `goto`s where they do not.
+///
+/// The image is decompiled as given. Where code patches itself, the patched
+/// instruction shows up as unknown and extra entry points can be given to
+/// carry on past it.
+#[derive(Debug, Clone)]
+pub struct Decompiler {
+    names: HashMap<usize, String>,
+    entries: Vec<usize>
+}
+
+impl Default for Decompiler {
+    fn default() -> Self {
+        Decompiler {
+            names: HashMap::new(),
+            entries: vec![0]
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operand {
+    Position(usize),
+    Immediate(i32)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BinaryOp {
+    Add,
+    Multiply,
+    LessThan,
+    Equals
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Flow {
+    Always,
+    Never,
+    Conditional
+}
+
+#[derive(Debug, Clone)]
+enum Kind {
+    Binary(BinaryOp, Operand, Operand, usize),
+    Input(usize),
+    Output(Operand),
+    Jump { if_true: bool, condition: Operand, target: Operand, resolved: Option<usize> },
+    Halt,
+    Unknown(i32)
+}
+
+#[derive(Debug, Clone)]
+struct Insn {
+    len: usize,
+    kind: Kind
+}
+
+impl Insn {
+    fn flow(&self) -> Option<Flow> {
+        match self.kind {
+            Kind::Jump { if_true, condition: Operand::Immediate(value), .. } => {
+                if (value != 0) == if_true { Some(Flow::Always) } else { Some(Flow::Never) }
+            },
+            Kind::Jump { .. } => Some(Flow::Conditional),
+            _ => None
+        }
+    }
+
+    fn target(&self) -> Option<usize> {
+        match self.kind {
+            Kind::Jump { resolved, .. } if self.flow() != Some(Flow::Never) => resolved,
+            _ => None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Loop {
+    head: usize,
+    exit: usize
+}
+
+struct Emitter<'a> {
+    insns: &'a BTreeMap<usize, Insn>,
+    names: &'a HashMap<usize, String>,
+    lines: Vec<(usize, usize, String)>,
+    labels: BTreeSet<usize>
+}
+
+impl Decompiler {
+    /// Shows the cell at `address` as `name` rather than `m[address]`.
+    pub fn name(mut self, address: usize, name: &str) -> Self {
+        self.names.insert(address, name.to_string());
+        self
+    }
+
+    /// Adds another address to start following control flow from, as well
+    /// as address 0.
+    pub fn entry(mut self, address: usize) -> Self {
+        self.entries.push(address);
+        self
+    }
+
+    /// Decompiles `memory`, returning one statement per line.
+    pub fn decompile(&self, memory: &[i32]) -> String {
+        let insns = self.analyse(memory);
+        let mut emitter = Emitter {
+            insns: &insns,
+            names: &self.names,
+            lines: Vec::new(),
+            labels: BTreeSet::new()
+        };
+
+        emitter.block(0, usize::MAX, 0, None);
+
+        let mut out = String::new();
+        let mut pending_labels = emitter.labels.clone();
+
+        for (address, depth, text) in emitter.lines {
+            let due: Vec<usize> = pending_labels.range(..=address).copied().collect();
+
+            for label in due {
+                pending_labels.remove(&label);
+                if insns.contains_key(&label) {
+                    out.push_str(&format!("{}L{}:\n", "    ".repeat(depth), label));
+                }
+            }
+
+            out.push_str(&format!("{}{}\n", "    ".repeat(depth), text));
+        }
+
+        out
+    }
+
+    // Follows control flow from the entry points. Constants are only carried
+    // along a run of code while nothing else can jump into it, so this is
+    // repeated, clearing them at every jump target found so far, until no
+    // new targets turn up. Once a jump can't be resolved it could land
+    // anywhere, so then no constants are carried at all.
+    fn analyse(&self, memory: &[i32]) -> BTreeMap<usize, Insn> {
+        let mut targets: BTreeSet<usize> = BTreeSet::new();
+        let mut unresolved = false;
+
+        loop {
+            let insns = self.follow(memory, &targets, unresolved);
+            let mut changed = false;
+
+            for insn in insns.values() {
+                match insn.kind {
+                    Kind::Jump { resolved: None, .. } if insn.flow() != Some(Flow::Never) => {
+                        changed |= !unresolved;
+                        unresolved = true;
+                    },
+                    _ => if let Some(target) = insn.target() {
+                        changed |= targets.insert(target);
+                    }
+                }
+            }
+
+            if !changed {
+                return insns;
+            }
+        }
+    }
+
+    fn follow(&self, memory: &[i32], targets: &BTreeSet<usize>, unresolved: bool) -> BTreeMap<usize, Insn> {
+        let mut insns = BTreeMap::new();
+        let mut work: Vec<usize> = self.entries.iter().rev().copied().collect();
+
+        while let Some(start) = work.pop() {
+            let mut pointer = start;
+            // Cells known to hold a constant on this straight-line run.
+            let mut known: HashMap<usize, i32> = HashMap::new();
+
+            while pointer < memory.len() && !insns.contains_key(&pointer) {
+                if unresolved || targets.contains(&pointer) {
+                    known.clear();
+                }
+
+                let mut insn = decode(memory, pointer);
+                let next = pointer + insn.len;
+
+                match &mut insn.kind {
+                    Kind::Binary(op, a, b, to) => {
+                        match (value_of(*a, &known), value_of(*b, &known)) {
+                            (Some(a), Some(b)) => match op.apply(a, b) {
+                                Some(value) => known.insert(*to, value),
+                                None => known.remove(to)
+                            },
+                            _ => known.remove(to)
+                        };
+                    },
+                    Kind::Input(to) => {
+                        known.remove(to);
+                    },
+                    Kind::Jump { target, resolved, .. } => {
+                        *resolved = match *target {
+                            Operand::Immediate(value) => usize::try_from(value).ok(),
+                            Operand::Position(address) => {
+                                known.get(&address).and_then(|value| usize::try_from(*value).ok())
+                            }
+                        };
+                    },
+                    Kind::Output(_) | Kind::Halt | Kind::Unknown(_) => {}
+                }
+
+                let flow = insn.flow();
+                let stop = match insn.kind {
+                    Kind::Halt | Kind::Unknown(_) => true,
+                    _ => flow == Some(Flow::Always)
+                };
+
+                if let Some(target) = insn.target() {
+                    work.push(target);
+                }
+
+                insns.insert(pointer, insn);
+
+                if stop {
+                    break;
+                }
+
+                pointer = next;
+            }
+        }
+
+        insns
+    }
+}
+
+impl BinaryOp {
+    fn apply(self, a: i32, b: i32) -> Option<i32> {
+        match self {
+            BinaryOp::Add => a.checked_add(b),
+            BinaryOp::Multiply => a.checked_mul(b),
+            BinaryOp::LessThan => Some((a < b) as i32),
+            BinaryOp::Equals => Some((a == b) as i32)
+        }
+    }
+}
+
+fn value_of(operand: Operand, known: &HashMap<usize, i32>) -> Option<i32> {
+    match operand {
+        Operand::Immediate(value) => Some(value),
+        Operand::Position(address) => known.get(&address).copied()
+    }
+}
+
+fn decode(memory: &[i32], pointer: usize) -> Insn {
+    let word = memory[pointer];
+    let unknown = Insn { len: 1, kind: Kind::Unknown(word) };
+
+    let instruction = match OpcodeWithParamModes::try_from(word) {
+        Ok(instruction) => instruction,
+        Err(_) => return unknown
+    };
+
+    let num_params = instruction.opcode.num_params();
+    let mut operands = Vec::with_capacity(num_params);
+
+    for i in 0..num_params {
+        let raw = match memory.get(pointer + i + 1) {
+            Some(raw) => *raw,
+            None => return unknown
+        };
+
+        operands.push(match instruction.param_modes[i] {
+            ParamMode::ImmediateMode if !instruction.opcode.writable_params().contains(&(i + 1)) => {
+                Operand::Immediate(raw)
+            },
+            _ => match usize::try_from(raw) {
+                Ok(address) => Operand::Position(address),
+                Err(_) => return unknown
+            }
+        });
+    }
+
+    let address = |operand: Operand| match operand {
+        Operand::Position(address) => address,
+        Operand::Immediate(_) => unreachable!()
+    };
+
+    let kind = match instruction.opcode {
+        Opcode::Add => Kind::Binary(BinaryOp::Add, operands[0], operands[1], address(operands[2])),
+        Opcode::Multiply => Kind::Binary(BinaryOp::Multiply, operands[0], operands[1], address(operands[2])),
+        Opcode::LessThan => Kind::Binary(BinaryOp::LessThan, operands[0], operands[1], address(operands[2])),
+        Opcode::Equals => Kind::Binary(BinaryOp::Equals, operands[0], operands[1], address(operands[2])),
+        Opcode::Input => Kind::Input(address(operands[0])),
+        Opcode::Output => Kind::Output(operands[0]),
+        Opcode::JumpIfTrue | Opcode::JumpIfFalse => Kind::Jump {
+            if_true: matches!(instruction.opcode, Opcode::JumpIfTrue),
+            condition: operands[0],
+            target: operands[1],
+            resolved: None
+        },
+        Opcode::Halt => Kind::Halt
+    };
+
+    Insn { len: num_params + 1, kind }
+}
+
+impl<'a> Emitter<'a> {
+    fn block(&mut self, lo: usize, hi: usize, depth: usize, current: Option<Loop>) {
+        let mut next = self.insns.range(lo..hi).next().map(|(address, _)| *address);
+
+        while let Some(address) = next {
+            let resume = self.statement(address, hi, depth, current);
+            next = self.insns.range(resume..hi).next().map(|(address, _)| *address);
+        }
+    }
+
+    // Emits the statement (or block) starting at `address` and returns the
+    // address to carry on from.
+    fn statement(&mut self, address: usize, hi: usize, depth: usize, current: Option<Loop>) -> usize {
+        let insn = &self.insns[&address];
+        let end = address + insn.len;
+
+        if current.map(|l| l.head) != Some(address) {
+            if let Some(resume) = self.try_loop(address, hi, depth) {
+                return resume;
+            }
+        }
+
+        let (if_true, condition, target) = match insn.kind {
+            Kind::Jump { if_true, condition, target, .. } => (if_true, condition, target),
+            _ => {
+                let text = self.simple(&insn.kind);
+                self.emit(address, depth, text);
+                return end;
+            }
+        };
+
+        let goto = |resolved: Option<usize>| match resolved {
+            Some(to) => format!("goto L{}", to),
+            None => format!("goto *{}", self.operand(target))
+        };
+
+        match (insn.flow(), insn.target()) {
+            (Some(Flow::Never), _) => {
+                let text = format!("// never jumps to {}", self.operand(target));
+                self.emit(address, depth, text);
+                end
+            },
+            (Some(Flow::Always), Some(to)) if current.map(|l| l.exit) == Some(to) => {
+                self.emit(address, depth, "break".to_string());
+                end
+            },
+            (Some(Flow::Always), Some(to)) if current.map(|l| l.head) == Some(to) => {
+                self.emit(address, depth, "continue".to_string());
+                end
+            },
+            (Some(Flow::Always), Some(to)) if to <= hi && self.insns.range(end..).next().map(|(a, _)| *a) == Some(to) => {
+                end
+            },
+            (Some(Flow::Always), resolved) => {
+                let text = goto(resolved);
+                self.label(resolved);
+                self.emit(address, depth, text);
+                end
+            },
+            (_, Some(to)) if current.map(|l| l.exit) == Some(to) => {
+                let text = format!("if ({}) break", self.condition(if_true, condition));
+                self.emit(address, depth, text);
+                end
+            },
+            (_, Some(to)) if current.map(|l| l.head) == Some(to) => {
+                let text = format!("if ({}) continue", self.condition(if_true, condition));
+                self.emit(address, depth, text);
+                end
+            },
+            (_, Some(to)) if address < to && self.closes(to, hi) => {
+                let text = format!("if ({}) {{", self.condition(!if_true, condition));
+                self.emit(address, depth, text);
+
+                let last = self.insns.range(end..to).next_back().map(|(a, insn)| (*a, insn.clone()));
+                let otherwise = match last {
+                    Some((last, ref insn)) if last + insn.len == to && insn.flow() == Some(Flow::Always) => {
+                        insn.target()
+                            .filter(|after| to < *after && self.closes(*after, hi))
+                            .filter(|after| current.is_none_or(|l| l.exit != *after && l.head != *after))
+                            .map(|after| (last, after))
+                    },
+                    _ => None
+                };
+
+                match otherwise {
+                    Some((last, after)) => {
+                        self.block(end, last, depth + 1, current);
+                        self.emit(to, depth, "} else {".to_string());
+                        self.block(to, after, depth + 1, current);
+                        self.emit(after, depth, "}".to_string());
+                        after
+                    },
+                    None => {
+                        self.block(end, to, depth + 1, current);
+                        self.emit(to, depth, "}".to_string());
+                        to
+                    }
+                }
+            },
+            (_, resolved) => {
+                let text = format!("if ({}) {}", self.condition(if_true, condition), goto(resolved));
+                self.label(resolved);
+                self.emit(address, depth, text);
+                end
+            }
+        }
+    }
+
+    // Opens a loop at `head` if a later jump in this block goes back to it.
+    fn try_loop(&mut self, head: usize, hi: usize, depth: usize) -> Option<usize> {
+        let (back, back_insn) = self.insns.range(head..hi)
+            .rfind(|(_, insn)| insn.target() == Some(head))
+            .map(|(address, insn)| (*address, insn.clone()))?;
+        let exit = back + back_insn.len;
+        let head_insn = self.insns[&head].clone();
+
+        if back_insn.flow() == Some(Flow::Always) {
+            if let (Some(Flow::Conditional), Some(to), Kind::Jump { if_true, condition, .. }) = (head_insn.flow(), head_insn.target(), &head_insn.kind) {
+                if to == exit {
+                    let text = format!("while ({}) {{", self.condition(!*if_true, *condition));
+                    self.emit(head, depth, text);
+                    self.block(head + head_insn.len, back, depth + 1, Some(Loop { head, exit }));
+                    self.emit(back, depth, "}".to_string());
+                    return Some(exit);
+                }
+            }
+
+            self.emit(head, depth, "loop {".to_string());
+            self.block(head, back, depth + 1, Some(Loop { head, exit }));
+            self.emit(back, depth, "}".to_string());
+        } else {
+            let condition = match back_insn.kind {
+                Kind::Jump { if_true, condition, .. } => self.condition(if_true, condition),
+                _ => unreachable!()
+            };
+
+            self.emit(head, depth, "do {".to_string());
+            self.block(head, back, depth + 1, Some(Loop { head, exit }));
+            self.emit(back, depth, format!("}} while ({})", condition));
+        }
+
+        Some(exit)
+    }
+
+    // Whether a block running up to `to` nests inside one ending at `hi`.
+    fn closes(&self, to: usize, hi: usize) -> bool {
+        to == hi || (to < hi && self.insns.contains_key(&to))
+    }
+
+    fn simple(&self, kind: &Kind) -> String {
+        match *kind {
+            Kind::Binary(op, a, b, to) => {
+                let expression = match (op, a, b) {
+                    (BinaryOp::Add, _, Operand::Immediate(b)) if b < 0 => {
+                        format!("{} - {}", self.operand(a), -(b as i64))
+                    },
+                    (BinaryOp::Add, Operand::Immediate(a), _) if a < 0 => {
+                        format!("{} - {}", self.operand(b), -(a as i64))
+                    },
+                    (BinaryOp::Add, _, _) => format!("{} + {}", self.operand(a), self.operand(b)),
+                    (BinaryOp::Multiply, _, _) => format!("{} * {}", self.operand(a), self.operand(b)),
+                    (BinaryOp::LessThan, _, _) => format!("{} < {}", self.operand(a), self.operand(b)),
+                    (BinaryOp::Equals, _, _) => format!("{} == {}", self.operand(a), self.operand(b))
+                };
+
+                format!("{} = {}", self.cell(to), expression)
+            },
+            Kind::Input(to) => format!("{} = input()", self.cell(to)),
+            Kind::Output(value) => format!("output({})", self.operand(value)),
+            Kind::Halt => "halt()".to_string(),
+            Kind::Unknown(word) => format!("// unknown instruction {}", word),
+            Kind::Jump { .. } => unreachable!()
+        }
+    }
+
+    fn condition(&self, if_true: bool, condition: Operand) -> String {
+        if if_true {
+            format!("{} != 0", self.operand(condition))
+        } else {
+            format!("{} == 0", self.operand(condition))
+        }
+    }
+
+    fn operand(&self, operand: Operand) -> String {
+        match operand {
+            Operand::Position(address) => self.cell(address),
+            Operand::Immediate(value) => value.to_string()
+        }
+    }
+
+    fn cell(&self, address: usize) -> String {
+        match self.names.get(&address) {
+            Some(name) => name.clone(),
+            None => format!("m[{}]", address)
+        }
+    }
+
+    fn label(&mut self, target: Option<usize>) {
+        if let Some(target) = target {
+            self.labels.insert(target);
+        }
+    }
+
+    fn emit(&mut self, address: usize, depth: usize, text: String) {
+        self.lines.push((address, depth, text));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Decompiler;
+
+    #[test]
+    fn decompiles_while_loops() {
+        let memory = [3, 100, 1006, 100, 14, 4, 100, 1001, 100, -1, 100, 1105, 1, 2, 99];
+
+        assert_eq!(Decompiler::default().decompile(&memory), "\
+m[100] = input()
+while (m[100] != 0) {
+    output(m[100])
+    m[100] = m[100] - 1
+}
+halt()
+");
+    }
+
+    #[test]
+    fn decompiles_if_else_with_names() {
+        let memory = [3, 20, 1007, 20, 10, 21, 1006, 21, 14, 104, 1, 1105, 1, 16, 104, 2, 99];
+        let decompiler = Decompiler::default().name(20, "x").name(21, "small");
+
+        assert_eq!(decompiler.decompile(&memory), "\
+x = input()
+small = x < 10
+if (small != 0) {
+    output(1)
+} else {
+    output(2)
+}
+halt()
+");
+    }
+
+    #[test]
+    fn forgets_constants_where_other_code_jumps_in() {
+        // The jump at 9 goes to 14 when the input is 0, but to wherever m[18]
+        // already points, 12, when it isn't, so it can't be resolved.
+        let memory = [3, 17, 1005, 17, 9, 1101, 0, 14, 18, 105, 1, 18, 99, 99, 104, 7, 99, 0, 12];
+
+        assert_eq!(Decompiler::default().decompile(&memory), "\
+m[17] = input()
+if (m[17] == 0) {
+    m[18] = 0 + 14
+}
+goto *m[18]
+");
+    }
+
+    #[test]
+    fn decompiles_do_while_loops_and_gotos() {
+        // Counts down from the input, then jumps into the middle of the loop.
+        let memory = [3, 30, 1001, 30, -1, 30, 4, 30, 1005, 30, 2, 1105, 1, 6];
+
+        assert_eq!(Decompiler::default().decompile(&memory), "\
+m[30] = input()
+do {
+    m[30] = m[30] - 1
+    L6:
+    output(m[30])
+} while (m[30] != 0)
+goto L6
+");
+    }
+}
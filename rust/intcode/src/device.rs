@@ -0,0 +1,127 @@
+//! Peripherals that can be mapped into a `Computer`'s address space.
+
+use std::fmt::Debug;
+use std::ops::Range;
+
+/// A peripheral that is mapped into a range of a `Computer`'s address space.
+///
+/// Timing semantics:
+///
+/// * Devices are accessed synchronously, on the interpreter's thread, at the
+///   exact moment the interpreter touches the address. There is no buffering.
+/// * Every access goes through the bus, including instruction fetch. The
+///   opcode word is read first, then each raw parameter word, then each
+///   position-mode parameter is dereferenced in parameter order (first,
+///   second, third).
+/// * A write happens once the instruction has computed its result, after all
+///   of its reads. Input (opcode 3) consumes the input value before the write.
+/// * An input instruction that has to wait for a value is fetched again, with
+///   all of its reads, when it is retried.
+/// * After an instruction retires, every attached device is ticked once, in
+///   the order they were attached. Halting, or waiting for input, does not
+///   tick.
+///
+/// Offsets passed to `read` and `write` are relative to the start of the
+/// mapped range.
+pub trait Device: Debug {
+    fn read(&mut self, offset: usize) -> i32;
+    fn write(&mut self, offset: usize, value: i32);
+    fn tick(&mut self) {}
+}
+
+#[derive(Debug)]
+pub(crate) struct MappedDevice {
+    pub range: Range<usize>,
+    pub device: Box<dyn Device>,
+}
+
+/// Counts the instructions retired since it was attached (or last written
+/// to).
+#[derive(Debug, Default)]
+pub struct Clock {
+    ticks: i32
+}
+
+impl Device for Clock {
+    fn read(&mut self, _offset: usize) -> i32 {
+        self.ticks
+    }
+
+    fn write(&mut self, _offset: usize, value: i32) {
+        self.ticks = value;
+    }
+
+    fn tick(&mut self) {
+        self.ticks = self.ticks.wrapping_add(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::{Clock, Device};
+    use crate::computer::Computer;
+
+    #[derive(Debug, PartialEq)]
+    enum Access {
+        Read(usize),
+        Write(usize, i32),
+        Tick
+    }
+
+    // Logs every access it sees and answers reads with ten times the offset.
+    #[derive(Debug)]
+    struct Recorder(Rc<RefCell<Vec<Access>>>);
+
+    impl Device for Recorder {
+        fn read(&mut self, offset: usize) -> i32 {
+            self.0.borrow_mut().push(Access::Read(offset));
+            offset as i32 * 10
+        }
+
+        fn write(&mut self, offset: usize, value: i32) {
+            self.0.borrow_mut().push(Access::Write(offset, value));
+        }
+
+        fn tick(&mut self) {
+            self.0.borrow_mut().push(Access::Tick);
+        }
+    }
+
+    #[test]
+    fn devices_see_accesses_in_order() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut computer = Computer::new("1,101,102,100,4,100,99", vec![]).unwrap();
+        computer.attach(100..103, Box::new(Recorder(log.clone()))).unwrap();
+
+        assert_eq!(computer.exec(), Some(0));
+        assert_eq!(*log.borrow(), vec![
+            Access::Read(1),
+            Access::Read(2),
+            Access::Write(0, 30),
+            Access::Tick,
+            Access::Read(0),
+            Access::Tick
+        ]);
+    }
+
+    #[test]
+    fn clock_counts_retired_instructions() {
+        let mut computer = Computer::new("1101,0,0,11,1101,0,0,11,4,100,99,0", vec![]).unwrap();
+        computer.attach(100..101, Box::new(Clock::default())).unwrap();
+
+        assert_eq!(computer.exec(), Some(2));
+    }
+
+    #[test]
+    fn devices_cannot_overlap() {
+        let mut computer = Computer::new("99", vec![]).unwrap();
+        computer.attach(10..20, Box::new(Clock::default())).unwrap();
+
+        assert!(computer.attach(19..25, Box::new(Clock::default())).is_err());
+        assert!(computer.attach(5..5, Box::new(Clock::default())).is_err());
+        assert!(computer.attach(20..25, Box::new(Clock::default())).is_ok());
+    }
+}
@@ -0,0 +1,164 @@
+//! Comparing memory images, as a list of changes or a hexdump-style table.
+
+use std::convert::TryFrom;
+
+use crate::decoder::OpcodeWithParamModes;
+
+/// A single memory cell that differs between two images. `None` means the
+/// address lies beyond the end of that image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub address: usize,
+    pub before: Option<i32>,
+    pub after: Option<i32>,
+    pub instruction: Option<String>
+}
+
+/// How `render` marks the cells that changed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Highlight {
+    Brackets,
+    Ansi
+}
+
+const ANSI_CHANGED: &str = "\u{1b}[1;31m";
+const ANSI_RESET: &str = "\u{1b}[0m";
+
+/// Lists every cell that differs between `before` and `after`, annotated
+/// with the instruction that decodes at that address in `after`.
+pub fn diff(before: &[i32], after: &[i32]) -> Vec<Change> {
+    (0..before.len().max(after.len()))
+        .filter_map(|address| {
+            let old = before.get(address).copied();
+            let new = after.get(address).copied();
+
+            if old == new {
+                return None;
+            }
+
+            Some(Change {
+                address,
+                before: old,
+                after: new,
+                instruction: decode(after, address)
+            })
+        })
+        .collect()
+}
+
+/// Renders `after` as a table of `words_per_row` words with the address of
+/// the first word of each row in the margin, highlighting every cell that
+/// differs from `before`. The changes are then listed underneath, each with
+/// the instruction that decodes at that address.
+pub fn render(before: &[i32], after: &[i32], words_per_row: usize, highlight: Highlight) -> String {
+    let len = before.len().max(after.len());
+    let changes = diff(before, after);
+
+    let cell = |address: usize| match after.get(address) {
+        Some(value) => value.to_string(),
+        None => "-".to_string()
+    };
+
+    let value_width = (0..len).map(|address| cell(address).len()).max().unwrap_or(1);
+    let address_width = len.saturating_sub(1).to_string().len();
+
+    let mut out = String::new();
+
+    for row_start in (0..len).step_by(words_per_row.max(1)) {
+        let row_end = (row_start + words_per_row.max(1)).min(len);
+
+        let cells: Vec<String> = (row_start..row_end)
+            .map(|address| {
+                let value = format!("{:>width$}", cell(address), width = value_width);
+                let changed = changes.iter().any(|change| change.address == address);
+
+                match (changed, highlight) {
+                    (false, _) => format!(" {} ", value),
+                    (true, Highlight::Brackets) => format!("[{}]", value),
+                    (true, Highlight::Ansi) => format!(" {}{}{} ", ANSI_CHANGED, value, ANSI_RESET)
+                }
+            })
+            .collect();
+
+        let line = format!("{:>width$} | {}", row_start, cells.join(" "), width = address_width);
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+
+    if !changes.is_empty() {
+        out.push('\n');
+    }
+
+    for change in changes {
+        let show = |value: Option<i32>| match value {
+            Some(value) => value.to_string(),
+            None => "-".to_string()
+        };
+
+        out.push_str(&format!(
+            "{:>width$}: {} -> {}",
+            change.address,
+            show(change.before),
+            show(change.after),
+            width = address_width
+        ));
+
+        if let Some(instruction) = change.instruction {
+            out.push_str(&format!(" ({})", instruction));
+        }
+
+        out.push('\n');
+    }
+
+    out
+}
+
+fn decode(memory: &[i32], address: usize) -> Option<String> {
+    let word = *memory.get(address)?;
+
+    OpcodeWithParamModes::try_from(word)
+        .ok()
+        .map(|instruction| instruction.disassemble(memory, address))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff, render, Change, Highlight};
+    use crate::computer::Computer;
+
+    #[test]
+    fn diffs_memory_before_and_after_exec() {
+        let mut computer = Computer::new("1,1,1,4,99,5,6,0,99", vec![]).unwrap();
+        let before = computer.memory().to_vec();
+        computer.exec();
+
+        assert_eq!(diff(&before, &computer.memory().to_vec()), vec![
+            Change { address: 0, before: Some(1), after: Some(30), instruction: None },
+            Change { address: 4, before: Some(99), after: Some(2), instruction: Some("mul [5], [6], [0]".to_string()) }
+        ]);
+
+        assert_eq!(render(&before, &computer.memory().to_vec(), 5, Highlight::Brackets), "\
+0 | [30]   1    1    4  [ 2]
+5 |   5    6    0   99
+
+0: 1 -> 30
+4: 99 -> 2 (mul [5], [6], [0])
+");
+    }
+
+    #[test]
+    fn diffs_images_of_different_lengths() {
+        let before = vec![1101, 100, -1, 4];
+        let after = vec![1101, 100, -1, 4, 99];
+
+        assert_eq!(diff(&before, &after), vec![
+            Change { address: 4, before: None, after: Some(99), instruction: Some("hlt".to_string()) }
+        ]);
+        assert_eq!(diff(&after, &before)[0].after, None);
+
+        assert_eq!(
+            render(&before, &after, 8, Highlight::Ansi).lines().next(),
+            Some("0 |  1101    100     -1      4   \u{1b}[1;31m  99\u{1b}[0m")
+        );
+    }
+}
@@ -0,0 +1,442 @@
+//! Checking that two programs give the same outputs for every input in a
+//! bounded domain, eg. to check an optimized program against the original.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+
+use crate::computer::{Computer, Status};
+use crate::decoder::OpcodeWithParamModes;
+use crate::error::Error;
+use crate::image::Image;
+use crate::observer::{Control, Observer};
+use crate::sweep::combinations;
+
+/// How a run stopped.
+#[derive(Debug, Clone, PartialEq)]
+pub enum End {
+    Halted,
+    AwaitingInput,
+    OutOfBudget,
+    Failed(Error)
+}
+
+impl End {
+    // Whether two runs stopped in the same way. Errors name addresses, which
+    // needn't match between programs, so any two failures agree.
+    fn agrees_with(&self, other: &End) -> bool {
+        match (self, other) {
+            (End::Failed(_), End::Failed(_)) => true,
+            _ => self == other
+        }
+    }
+}
+
+impl fmt::Display for End {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            End::Halted => write!(f, "halted"),
+            End::AwaitingInput => write!(f, "needed more input"),
+            End::OutOfBudget => write!(f, "used up its budget"),
+            End::Failed(err) => write!(f, "failed: {}", err)
+        }
+    }
+}
+
+/// One program's run on the input of a `CounterExample`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Run {
+    pub outputs: Vec<i32>,
+    pub end: End,
+    /// Each instruction that ran, as its address and disassembly, and each
+    /// value output, as `=> value`.
+    pub trace: Vec<String>
+}
+
+/// An input the two programs disagree on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CounterExample {
+    pub input: Vec<i32>,
+    pub left: Run,
+    pub right: Run
+}
+
+impl CounterExample {
+    /// Why the runs disagree.
+    pub fn reason(&self) -> String {
+        let (left, right) = (&self.left.outputs, &self.right.outputs);
+
+        match left.iter().zip(right).position(|(a, b)| a != b) {
+            Some(i) => format!("Output {} is {} on the left but {} on the right.", i, left[i], right[i]),
+            None if left.len() != right.len() => {
+                format!("The left gives {} outputs but the right gives {}.", left.len(), right.len())
+            },
+            None => format!("The left {} but the right {}.", self.left.end, self.right.end)
+        }
+    }
+
+    /// The two traces side by side, lined up so that their outputs share a
+    /// row, up to the first output that differs. Rows where the two disagree
+    /// on an output are marked with a `!`.
+    pub fn side_by_side(&self) -> String {
+        let left = segments(&self.left.trace);
+        let right = segments(&self.right.trace);
+        let width = self.left.trace.iter().map(String::len).max().unwrap_or(0).max("left".len());
+
+        let mut out = format!("  {:<width$} | right\n", "left", width = width);
+
+        for i in 0..left.len().max(right.len()) {
+            let (a, b) = (left.get(i).map_or(&[][..], Vec::as_slice), right.get(i).map_or(&[][..], Vec::as_slice));
+            let (a_steps, a_output) = split_output(a);
+            let (b_steps, b_output) = split_output(b);
+            let differs = a_output != b_output;
+
+            let mut rows: Vec<(char, String, String)> = (0..a_steps.len().max(b_steps.len()))
+                .map(|row| (' ', a_steps.get(row).cloned().unwrap_or_default(), b_steps.get(row).cloned().unwrap_or_default()))
+                .collect();
+
+            if a_output.is_some() || b_output.is_some() {
+                let mark = if differs { '!' } else { ' ' };
+                rows.push((mark, a_output.cloned().unwrap_or_default(), b_output.cloned().unwrap_or_default()));
+            }
+
+            for (mark, a, b) in rows {
+                let line = format!("{} {:<width$} | {}", mark, a, b, width = width);
+                out.push_str(line.trim_end());
+                out.push('\n');
+            }
+
+            if differs && i < left.len().min(right.len()) {
+                break;
+            }
+        }
+
+        out
+    }
+}
+
+impl fmt::Display for CounterExample {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "The programs differ for input {:?}. {}", self.input, self.reason())?;
+        writeln!(f)?;
+        write!(f, "{}", self.side_by_side())
+    }
+}
+
+/// Why `Equivalence::check` failed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CheckError {
+    /// The programs disagree on an input.
+    Differ(Box<CounterExample>),
+    /// The domain could not be checked.
+    Invalid(Error)
+}
+
+impl fmt::Display for CheckError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CheckError::Differ(counter_example) => write!(f, "{}", counter_example),
+            CheckError::Invalid(err) => write!(f, "{}", err)
+        }
+    }
+}
+
+// Splits a trace after each output, with the output as the last line of its
+// segment. The instructions after the last output are a segment of their
+// own.
+fn segments(trace: &[String]) -> Vec<Vec<String>> {
+    let mut segments = vec![Vec::new()];
+
+    for line in trace {
+        segments.last_mut().unwrap().push(line.clone());
+
+        if is_output(line) {
+            segments.push(Vec::new());
+        }
+    }
+
+    if segments.last().is_some_and(Vec::is_empty) {
+        segments.pop();
+    }
+
+    segments
+}
+
+// The instructions of a segment, and the output it ends with, if it does.
+fn split_output(segment: &[String]) -> (&[String], Option<&String>) {
+    match segment.split_last() {
+        Some((last, steps)) if is_output(last) => (steps, Some(last)),
+        _ => (segment, None)
+    }
+}
+
+fn is_output(line: &str) -> bool {
+    line.trim_start().starts_with("=>")
+}
+
+/// What `Equivalence::check` found when the programs agreed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checked {
+    /// How many inputs were tried.
+    pub inputs: usize,
+    /// How many of those both programs used up their budget on, agreeing on
+    /// every output up to that point.
+    pub out_of_budget: usize
+}
+
+/// Checks that two programs give the same outputs, and stop in the same way,
+/// for every input in a bounded domain.
+///
+/// The domain is built like a `Sweep`: one range per input value, with every
+/// combination tried in order and the last range varying fastest. Domains
+/// with more combinations than fit in a `usize` fail with
+/// `Error::DomainTooLarge`.
+///
+/// ```
+/// use intcode::equivalence::Equivalence;
+///
+/// // Both output whether the input is 8, the second one inside out.
+/// let left = vec![3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8];
+/// let right = vec![3, 3, 1108, -1, 8, 3, 4, 3, 99];
+///
+/// let checked = Equivalence::new(left, right).input(-100..=100).check().unwrap();
+/// assert_eq!(checked.inputs, 201);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Equivalence {
+    left: Image,
+    right: Image,
+    inputs: Vec<RangeInclusive<i32>>,
+    budget: usize
+}
+
+impl Equivalence {
+    /// Compares `left` with `right`, with no inputs and a budget of 100,000
+    /// instructions per run.
+    pub fn new(left: Vec<i32>, right: Vec<i32>) -> Self {
+        Equivalence {
+            left: Image::new(left),
+            right: Image::new(right),
+            inputs: Vec::new(),
+            budget: 100_000
+        }
+    }
+
+    /// Adds an input value, varied over `values`.
+    pub fn input(mut self, values: RangeInclusive<i32>) -> Self {
+        self.inputs.push(values);
+        self
+    }
+
+    /// Sets how many instructions each run may execute.
+    pub fn budget(mut self, budget: usize) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Runs both programs on every input in the domain, returning the first
+    /// input they disagree on, with a trace of each run on it.
+    pub fn check(&self) -> Result<Checked, CheckError> {
+        let mut checked = Checked { inputs: 0, out_of_budget: 0 };
+        let mut input: Vec<i32> = self.inputs.iter().map(|values| *values.start()).collect();
+
+        match combinations(&self.inputs) {
+            None => return Err(CheckError::Invalid(Error::DomainTooLarge)),
+            Some(0) => return Ok(checked),
+            Some(_) => {}
+        }
+
+        loop {
+            let left = run(&self.left, &input, self.budget, false);
+            let right = run(&self.right, &input, self.budget, false);
+            checked.inputs += 1;
+
+            if left.outputs != right.outputs || !left.end.agrees_with(&right.end) {
+                return Err(CheckError::Differ(Box::new(CounterExample {
+                    left: run(&self.left, &input, self.budget, true),
+                    right: run(&self.right, &input, self.budget, true),
+                    input
+                })));
+            }
+
+            if left.end == End::OutOfBudget {
+                checked.out_of_budget += 1;
+            }
+
+            if !self.advance(&mut input) {
+                return Ok(checked);
+            }
+        }
+    }
+
+    // Moves on to the next input in the domain, returning `false` once every
+    // one has been tried.
+    fn advance(&self, input: &mut [i32]) -> bool {
+        for (value, values) in input.iter_mut().zip(&self.inputs).rev() {
+            if *value < *values.end() {
+                *value += 1;
+                return true;
+            }
+
+            *value = *values.start();
+        }
+
+        false
+    }
+}
+
+fn run(image: &Image, input: &[i32], budget: usize, trace: bool) -> Run {
+    let mut computer = Computer::with_image(image.clone(), input.to_vec());
+    let lines = Rc::new(RefCell::new(Lines::default()));
+
+    if trace {
+        computer.observe(Box::new(lines.clone()));
+    }
+
+    let end = match computer.run_for(budget) {
+        Ok(Status::Halted) => End::Halted,
+        Ok(Status::AwaitingInput) => End::AwaitingInput,
+        Ok(_) => End::OutOfBudget,
+        Err(err) => End::Failed(err)
+    };
+
+    let trace = lines.borrow_mut().lines.split_off(0);
+    Run { outputs: computer.outputs().to_vec(), end, trace }
+}
+
+// Records each instruction and output, for `Run::trace`.
+#[derive(Debug, Default)]
+struct Lines {
+    lines: Vec<String>,
+    fetching: Option<Fetching>
+}
+
+// The instruction on the last line, and the words fetched for it so far.
+#[derive(Debug)]
+struct Fetching {
+    pointer: usize,
+    instruction: OpcodeWithParamModes,
+    words: Vec<i32>
+}
+
+impl Fetching {
+    // Params that are yet to be fetched are shown as `?`.
+    fn line(&self) -> String {
+        format!("{:>5}  {}", self.pointer, self.instruction.disassemble(&self.words, 0))
+    }
+}
+
+impl Observer for Lines {
+    fn before(&mut self, computer: &Computer, instruction: &OpcodeWithParamModes) -> Control {
+        // The opcode has been fetched already, and `disassemble` only needs
+        // the params after it.
+        let fetching = Fetching { pointer: computer.pointer(), instruction: instruction.clone(), words: vec![0] };
+
+        self.lines.push(fetching.line());
+        self.fetching = Some(fetching);
+        Control::Continue
+    }
+
+    fn fetch(&mut self, address: usize, value: i32) {
+        if let Some(fetching) = &mut self.fetching {
+            if fetching.words.len() <= fetching.instruction.opcode.num_params() && address == fetching.pointer + fetching.words.len() {
+                fetching.words.push(value);
+                *self.lines.last_mut().unwrap() = fetching.line();
+            }
+        }
+    }
+
+    fn output(&mut self, value: i32) {
+        self.fetching = None;
+        self.lines.push(format!("{:>5}  => {}", "", value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CheckError, CounterExample, End, Equivalence};
+    use crate::compile::compile;
+    use crate::error::Error;
+    use crate::optimize::optimize;
+
+    fn counter_example(equivalence: Equivalence) -> CounterExample {
+        match equivalence.check() {
+            Err(CheckError::Differ(counter_example)) => *counter_example,
+            other => panic!("Expected a counter-example but got {:?}", other)
+        }
+    }
+
+    // Outputs 999, 1000 or 1001 for an input below, equal to or above 8, from
+    // the day 5 examples.
+    const COMPARE_TO_8: [i32; 47] = [
+        3, 21, 1008, 21, 8, 20, 1005, 20, 22, 107, 8, 21, 20, 1006, 20, 31, 1106, 0, 36, 98, 0, 0,
+        1002, 21, 125, 20, 4, 20, 1105, 1, 46, 104, 999, 1105, 1, 46, 1101, 1000, 1, 20, 4, 20,
+        1105, 1, 46, 98, 99
+    ];
+
+    #[test]
+    fn checks_reimplementations_and_optimized_programs() {
+        let compiled = compile("
+            n = input();
+            if n < 8 { output(999); } else if n == 8 { output(1000); } else { output(1001); }
+        ").unwrap();
+
+        let checked = Equivalence::new(COMPARE_TO_8.to_vec(), compiled.memory).input(-100..=100).check().unwrap();
+        assert_eq!((checked.inputs, checked.out_of_budget), (201, 0));
+
+        let optimized = optimize(&COMPARE_TO_8).memory;
+        assert!(Equivalence::new(COMPARE_TO_8.to_vec(), optimized).input(-100..=100).check().is_ok());
+
+        // Two inputs, summed or multiplied, agree only where that's the same.
+        let sum = compile("output(input() + input());").unwrap().memory;
+        let product = compile("output(input() * input());").unwrap().memory;
+        let counter_example = counter_example(Equivalence::new(sum, product).input(0..=3).input(0..=3));
+        assert_eq!(counter_example.input, vec![0, 1]);
+    }
+
+    #[test]
+    fn reports_a_counter_example_side_by_side() {
+        let wrong = compile("
+            n = input();
+            if n < 8 { output(999); } else if n > 8 { output(1001); } else { output(1001); }
+        ").unwrap();
+
+        let counter_example = counter_example(Equivalence::new(COMPARE_TO_8.to_vec(), wrong.memory).input(-100..=100));
+        assert_eq!(counter_example.input, vec![8]);
+        assert_eq!((counter_example.left.end.clone(), counter_example.right.end.clone()), (End::Halted, End::Halted));
+        assert_eq!(counter_example.to_string(), "\
+The programs differ for input [8]. Output 0 is 1000 on the left but 1001 on the right.
+
+  left                       | right
+      0  in [21]             |     0  in [34]
+      2  eq [21], 8, [20]    |     2  add [34], 0, [33]
+      6  jt [20], 22         |     6  lt [33], 8, [34]
+     22  mul [21], 125, [20] |    10  jf [34], 18
+     26  out [20]            |    18  lt 8, [33], [34]
+                             |    22  jf [34], 30
+                             |    30  out 1001
+!        => 1000             |        => 1001
+");
+    }
+
+    #[test]
+    fn stops_and_budgets_must_agree() {
+        let halts = vec![99];
+        let waits = vec![3, 0, 99];
+        let loops = vec![1105, 1, 0];
+
+        let counter_example = counter_example(Equivalence::new(halts, waits));
+        assert_eq!(counter_example.reason(), "The left halted but the right needed more input.");
+
+        let checked = Equivalence::new(loops.clone(), loops).budget(50).input(1..=3).check().unwrap();
+        assert_eq!((checked.inputs, checked.out_of_budget), (3, 3));
+    }
+
+    #[test]
+    fn rejects_domains_too_large_to_count() {
+        let all = i32::MIN..=i32::MAX;
+        let equivalence = Equivalence::new(vec![99], vec![99]).input(all.clone()).input(all.clone()).input(all);
+        assert_eq!(equivalence.check(), Err(CheckError::Invalid(Error::DomainTooLarge)));
+    }
+}
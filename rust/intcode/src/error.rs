@@ -0,0 +1,75 @@
+//! The errors that loading or running a program can fail with.
+
+use std::fmt;
+use std::ops::Range;
+
+use crate::decoder::Opcode;
+use crate::format::ParseError;
+use crate::protection::{Access, Protection};
+
+/// Everything that can go wrong loading, setting up or running a `Computer`.
+///
+/// Errors raised while running carry the `pointer` of the instruction that
+/// was executing.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// The program image could not be parsed.
+    Parse(ParseError),
+    /// The word at `pointer` is not a valid instruction.
+    InvalidInstruction { pointer: usize, reason: String },
+    /// The instruction at `pointer` used an address that is negative or lies
+    /// outside of memory and every mapped device.
+    InvalidAddress { pointer: usize, address: i64 },
+    /// The instruction at `pointer` overflowed computing `a` and `b` under
+    /// `Arithmetic::Checked`.
+    Overflow { pointer: usize, opcode: Opcode, a: i32, b: i32 },
+    /// The instruction at `pointer` tried an `access` of `address` that the
+    /// `protection` of `region` forbids.
+    Protected { pointer: usize, address: usize, access: Access, protection: Protection, region: Range<usize> },
+    /// A patch was given for an address outside of memory.
+    InvalidPatch { address: usize, len: usize },
+    /// A device or protection could not be mapped to `range`.
+    InvalidMapping { range: Range<usize>, reason: String },
+    /// A sweep or equivalence check was given more combinations of values
+    /// than can be counted.
+    DomainTooLarge
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Parse(err) => write!(f, "{}", err),
+            Error::InvalidInstruction { pointer, reason } => {
+                write!(f, "Invalid instruction at {}: {}", pointer, reason)
+            },
+            Error::InvalidAddress { pointer, address } => {
+                write!(f, "Instruction at {} accessed invalid address {}.", pointer, address)
+            },
+            Error::Overflow { pointer, opcode, a, b } => {
+                write!(f, "Instruction at {} overflowed computing {:?} of {} and {}.", pointer, opcode, a, b)
+            },
+            Error::Protected { pointer, address, access, protection, region } => {
+                write!(
+                    f,
+                    "Instruction at {} tried to {} address {}, which is in {} region {:?}.",
+                    pointer, access, address, protection, region
+                )
+            },
+            Error::InvalidPatch { address, len } => {
+                write!(f, "Cannot patch address {} as memory is only {} words long.", address, len)
+            },
+            Error::InvalidMapping { range, reason } => {
+                write!(f, "Cannot map anything to {:?}: {}", range, reason)
+            },
+            Error::DomainTooLarge => write!(f, "The domain has more than {} combinations.", usize::MAX)
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<ParseError> for Error {
+    fn from(err: ParseError) -> Self {
+        Error::Parse(err)
+    }
+}
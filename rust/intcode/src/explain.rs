@@ -0,0 +1,211 @@
+//! Describing each instruction as it runs in plain English, for anyone new to
+//! Intcode.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::computer::Computer;
+use crate::decoder::{Opcode, OpcodeWithParamModes, ParamMode};
+use crate::error::Error;
+use crate::observer::{Control, Observer};
+
+#[derive(Debug)]
+struct Pending {
+    pointer: usize,
+    instruction: OpcodeWithParamModes,
+    // Each raw param, as it was fetched.
+    raws: Vec<Option<i32>>,
+    reads: Vec<i32>,
+    written: Option<(usize, i32)>,
+    input: Option<i32>
+}
+
+/// Writes a sentence for each instruction that runs, eg.
+/// `at 6: multiply 14 (immediate) by the value at address 224 (= 5) and store
+/// 70 at address 223`.
+///
+/// The params and values are the ones the instruction fetched and read while
+/// it ran, so a word read from a device is shown as the device gave it. An
+/// input instruction waiting for a value is only described once it has one.
+#[derive(Debug, Default)]
+pub struct Explainer {
+    pub lines: Vec<String>,
+    pending: Option<Pending>
+}
+
+impl Explainer {
+    fn finish(&mut self) {
+        let pending = match self.pending.take() {
+            Some(pending) => pending,
+            None => return
+        };
+
+        let operands = operands(&pending);
+        let operand = |i: usize| &operands[i].0;
+        let stored = || match pending.written {
+            Some((address, value)) => format!("{} at address {}", value, address),
+            None => "nothing".to_string()
+        };
+        let truth = || match pending.written {
+            Some((_, 0)) => " (false)",
+            _ => " (true)"
+        };
+
+        let sentence = match pending.instruction.opcode {
+            Opcode::Add => format!("add {} to {} and store {}", operand(0), operand(1), stored()),
+            Opcode::Multiply => format!("multiply {} by {} and store {}", operand(0), operand(1), stored()),
+            Opcode::Input => match (pending.input, pending.written) {
+                (Some(value), Some((address, _))) => format!("read {} from input and store it at address {}", value, address),
+                _ => "read from input".to_string()
+            },
+            Opcode::Output => format!("output {}", operand(0)),
+            Opcode::JumpIfTrue if operands[0].1 != 0 => format!("jump to {}, as {} is not zero", operand(1), operand(0)),
+            Opcode::JumpIfTrue => format!("carry on, as {} is zero", operand(0)),
+            Opcode::JumpIfFalse if operands[0].1 == 0 => format!("jump to {}, as {} is zero", operand(1), operand(0)),
+            Opcode::JumpIfFalse => format!("carry on, as {} is not zero", operand(0)),
+            Opcode::LessThan => format!("check whether {} is less than {} and store {}{}", operand(0), operand(1), stored(), truth()),
+            Opcode::Equals => format!("check whether {} equals {} and store {}{}", operand(0), operand(1), stored(), truth()),
+            Opcode::Halt => "halt".to_string()
+        };
+
+        self.lines.push(format!("at {}: {}", pending.pointer, sentence));
+    }
+}
+
+// Describes each param that is read, along with its value, in order.
+fn operands(pending: &Pending) -> Vec<(String, i32)> {
+    let writable = pending.instruction.opcode.writable_params();
+    let mut reads = pending.reads.iter();
+
+    (0..pending.instruction.opcode.num_params())
+        .filter(|i| !writable.contains(&(i + 1)))
+        .map(|i| {
+            let raw = pending.raws[i].unwrap_or_default();
+
+            match pending.instruction.param_modes[i] {
+                ParamMode::ImmediateMode => (format!("{} (immediate)", raw), raw),
+                ParamMode::PositionMode => {
+                    let value = reads.next().copied().unwrap_or_default();
+                    (format!("the value at address {} (= {})", raw, value), value)
+                }
+            }
+        })
+        .collect()
+}
+
+impl Observer for Explainer {
+    fn before(&mut self, computer: &Computer, instruction: &OpcodeWithParamModes) -> Control {
+        self.pending = Some(Pending {
+            pointer: computer.pointer(),
+            instruction: instruction.clone(),
+            raws: vec![None; instruction.opcode.num_params()],
+            reads: Vec::new(),
+            written: None,
+            input: None
+        });
+
+        Control::Continue
+    }
+
+    fn after(&mut self, _computer: &Computer, _pointer: usize, _instruction: &OpcodeWithParamModes) {
+        self.finish();
+    }
+
+    fn fetch(&mut self, address: usize, value: i32) {
+        if let Some(pending) = &mut self.pending {
+            if let Some(raw) = address.checked_sub(pending.pointer + 1).and_then(|i| pending.raws.get_mut(i)) {
+                *raw = Some(value);
+            }
+        }
+    }
+
+    fn read(&mut self, _address: usize, value: i32) {
+        if let Some(pending) = &mut self.pending {
+            pending.reads.push(value);
+        }
+    }
+
+    fn write(&mut self, address: usize, value: i32) {
+        if let Some(pending) = &mut self.pending {
+            pending.written = Some((address, value));
+        }
+    }
+
+    fn input(&mut self, value: i32) {
+        if let Some(pending) = &mut self.pending {
+            pending.input = Some(value);
+        }
+    }
+
+    fn halt(&mut self, _computer: &Computer) {
+        self.finish();
+    }
+}
+
+/// Runs `memory` with `input` until it halts or needs more input, returning
+/// a sentence for each instruction that ran.
+pub fn explain(memory: &[i32], input: Vec<i32>) -> Result<Vec<String>, Error> {
+    let explainer = Rc::new(RefCell::new(Explainer::default()));
+    let mut computer = Computer::with_memory(memory.to_vec(), input);
+    computer.observe(Box::new(explainer.clone()));
+    computer.run()?;
+
+    let lines = explainer.borrow().lines.clone();
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::{explain, Explainer};
+    use crate::computer::Computer;
+    use crate::device::Clock;
+    use crate::error::Error;
+
+    #[test]
+    fn explains_arithmetic_and_io() {
+        let lines = explain(&[3, 9, 1002, 9, 14, 10, 4, 10, 99, 0, 0], vec![5]).unwrap();
+
+        assert_eq!(lines, vec![
+            "at 0: read 5 from input and store it at address 9",
+            "at 2: multiply the value at address 9 (= 5) by 14 (immediate) and store 70 at address 10",
+            "at 6: output the value at address 10 (= 70)",
+            "at 8: halt"
+        ]);
+    }
+
+    #[test]
+    fn explains_jumps_and_comparisons() {
+        // Outputs whether the input equals 8, as in day 5.
+        let program = [3, 12, 1008, 12, 8, 13, 1005, 13, 11, 104, 0, 99, 0, 0];
+
+        assert_eq!(explain(&program, vec![8]).unwrap()[1..4].to_vec(), vec![
+            "at 2: check whether the value at address 12 (= 8) equals 8 (immediate) and store 1 at address 13 (true)",
+            "at 6: jump to 11 (immediate), as the value at address 13 (= 1) is not zero",
+            "at 11: halt"
+        ]);
+        assert_eq!(explain(&program, vec![7]).unwrap()[2..4].to_vec(), vec![
+            "at 6: carry on, as the value at address 13 (= 0) is zero",
+            "at 9: output 0 (immediate)"
+        ]);
+
+        assert_eq!(explain(&program, vec![]).unwrap(), Vec::<String>::new());
+        assert!(matches!(explain(&[1, 0, 0, 50], vec![]), Err(Error::InvalidAddress { .. })));
+    }
+
+    #[test]
+    fn explains_params_fetched_from_devices() {
+        // The second output's param is a clock, which has ticked once by then.
+        let mut computer = Computer::with_memory(vec![104, 0, 104, 0, 99], vec![]);
+        computer.attach(3..4, Box::new(Clock::default())).unwrap();
+
+        let explainer = Rc::new(RefCell::new(Explainer::default()));
+        computer.observe(Box::new(explainer.clone()));
+        computer.run().unwrap();
+
+        assert_eq!(computer.outputs(), &[0, 1]);
+        assert_eq!(explainer.borrow().lines[1], "at 2: output 1 (immediate)");
+    }
+}
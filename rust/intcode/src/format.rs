@@ -0,0 +1,333 @@
+//! Loading and writing program images.
+
+use std::convert::TryInto;
+use std::fmt;
+
+/// Program images can be stored in any of these formats:
+///
+/// * `CommaSeparated`: the puzzle input format, eg. `1,9,10,3,2,3,11,0,99`.
+/// * `LinePerWord`: one word per line.
+/// * `Binary`: the magic bytes `INTC`, a little-endian `u32` word count, then
+///   each word as a little-endian `i32`.
+/// * `Annotated`: comma-separated words spread over lines, where `#` starts a
+///   comment and a line may start with an `address:` prefix to place its words
+///   at that address. Any gap before an address is filled with zeroes, and
+///   addresses may be at most `MAX_ADDRESS`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    CommaSeparated,
+    LinePerWord,
+    Binary,
+    Annotated
+}
+
+const MAGIC: &[u8; 4] = b"INTC";
+const HEADER_LEN: usize = 8;
+const WORDS_PER_LINE: usize = 8;
+
+/// The highest address an `Annotated` image may place words at, so that a
+/// stray prefix can't fill gigabytes with zeroes.
+pub const MAX_ADDRESS: usize = 1 << 20;
+
+/// Where in an image a `ParseError` was found. Lines and columns are 1 based.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Position {
+    Text { line: usize, column: usize },
+    Byte(usize)
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Position::Text { line, column } => write!(f, "line {}, column {}", line, column),
+            Position::Byte(offset) => write!(f, "byte {}", offset)
+        }
+    }
+}
+
+/// A token in an image that could not be parsed, and why.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub token: String,
+    pub position: Position,
+    pub reason: String
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Unexpected '{}' at {}: {}", self.token, self.position, self.reason)
+    }
+}
+
+/// Guesses the format of an image from its contents.
+pub fn detect(bytes: &[u8]) -> Format {
+    if bytes.starts_with(MAGIC) {
+        return Format::Binary;
+    }
+
+    let text = String::from_utf8_lossy(bytes);
+    let lines: Vec<&str> = text.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .collect();
+
+    if lines.iter().any(|line| line.contains('#') || line.contains(':')) {
+        Format::Annotated
+    } else if lines.len() > 1 && lines.iter().all(|line| !line.contains(',')) {
+        Format::LinePerWord
+    } else {
+        Format::CommaSeparated
+    }
+}
+
+/// Parses an image in whichever format `detect` finds.
+pub fn parse(bytes: &[u8]) -> Result<Vec<i32>, ParseError> {
+    parse_as(detect(bytes), bytes)
+}
+
+pub fn parse_as(format: Format, bytes: &[u8]) -> Result<Vec<i32>, ParseError> {
+    if format == Format::Binary {
+        return parse_binary(bytes);
+    }
+
+    let text = std::str::from_utf8(bytes).map_err(|e| ParseError {
+        token: format!("{:#04x}", bytes[e.valid_up_to()]),
+        position: Position::Byte(e.valid_up_to()),
+        reason: "Text images must be valid UTF-8.".to_string()
+    })?;
+
+    match format {
+        Format::CommaSeparated => {
+            tokens(text, 0, 0, ',')
+                .map(|(token, position)| parse_word(token, position))
+                .collect()
+        },
+        Format::LinePerWord => {
+            tokens(text, 0, 0, '\n')
+                .map(|(token, position)| parse_word(token, position))
+                .collect()
+        },
+        Format::Annotated => parse_annotated(text),
+        Format::Binary => unreachable!()
+    }
+}
+
+/// Writes memory out as an image in the given format.
+pub fn write(format: Format, memory: &[i32]) -> Vec<u8> {
+    match format {
+        Format::CommaSeparated => format!("{}\n", join(memory)).into_bytes(),
+        Format::LinePerWord => {
+            memory.iter()
+                .map(|word| format!("{}\n", word))
+                .collect::<String>()
+                .into_bytes()
+        },
+        Format::Binary => {
+            let mut bytes = Vec::with_capacity(HEADER_LEN + memory.len() * 4);
+            bytes.extend_from_slice(MAGIC);
+            bytes.extend_from_slice(&(memory.len() as u32).to_le_bytes());
+
+            for word in memory {
+                bytes.extend_from_slice(&word.to_le_bytes());
+            }
+
+            bytes
+        },
+        Format::Annotated => {
+            let mut text = format!("# {} words\n", memory.len());
+
+            for (row, words) in memory.chunks(WORDS_PER_LINE).enumerate() {
+                text.push_str(&format!("{}: {}\n", row * WORDS_PER_LINE, join(words)));
+            }
+
+            text.into_bytes()
+        }
+    }
+}
+
+fn join(words: &[i32]) -> String {
+    words.iter().map(|word| word.to_string()).collect::<Vec<_>>().join(",")
+}
+
+// Splits `text` on `separator`, yielding each trimmed, non-empty token along
+// with the position of its first character. `line` and `column` give the
+// (zero based) position `text` itself starts at.
+fn tokens(text: &str, line: usize, column: usize, separator: char) -> impl Iterator<Item = (&str, Position)> {
+    let mut offset = 0;
+
+    text.split(separator).filter_map(move |raw| {
+        let start = offset + (raw.len() - raw.trim_start().len());
+        offset += raw.len() + separator.len_utf8();
+
+        let token = raw.trim();
+        if token.is_empty() {
+            return None;
+        }
+
+        let before = &text[..start];
+        let token_line = line + before.matches('\n').count();
+        let token_column = match before.rfind('\n') {
+            Some(newline) => start - newline - 1,
+            None => column + start
+        };
+
+        Some((token, Position::Text { line: token_line + 1, column: token_column + 1 }))
+    })
+}
+
+fn parse_word(token: &str, position: Position) -> Result<i32, ParseError> {
+    token.parse::<i32>().map_err(|e| ParseError {
+        token: token.to_string(),
+        position,
+        reason: format!("Failed to parse memory as i32: {}", e)
+    })
+}
+
+fn parse_annotated(text: &str) -> Result<Vec<i32>, ParseError> {
+    let mut memory = Vec::new();
+
+    for (line_index, line) in text.lines().enumerate() {
+        let code = match line.find('#') {
+            Some(comment) => &line[..comment],
+            None => line
+        };
+
+        let (words, column) = match code.find(':') {
+            Some(colon) => {
+                let raw_address = &code[..colon];
+                let address_token = raw_address.trim();
+                let position = Position::Text {
+                    line: line_index + 1,
+                    column: raw_address.len() - raw_address.trim_start().len() + 1
+                };
+
+                let address = address_token.parse::<usize>().map_err(|e| ParseError {
+                    token: address_token.to_string(),
+                    position,
+                    reason: format!("Failed to parse address as usize: {}", e)
+                })?;
+
+                if address > MAX_ADDRESS {
+                    return Err(ParseError {
+                        token: address_token.to_string(),
+                        position,
+                        reason: format!("Address is past the highest allowed, {}.", MAX_ADDRESS)
+                    });
+                }
+
+                if address < memory.len() {
+                    return Err(ParseError {
+                        token: address_token.to_string(),
+                        position,
+                        reason: format!("Address overlaps the {} words already loaded.", memory.len())
+                    });
+                }
+
+                memory.resize(address, 0);
+                (&code[colon + 1..], colon + 1)
+            },
+            None => (code, 0)
+        };
+
+        for (token, position) in tokens(words, line_index, column, ',') {
+            memory.push(parse_word(token, position)?);
+        }
+    }
+
+    Ok(memory)
+}
+
+fn parse_binary(bytes: &[u8]) -> Result<Vec<i32>, ParseError> {
+    if !bytes.starts_with(MAGIC) {
+        return Err(ParseError {
+            token: hex(&bytes[..bytes.len().min(MAGIC.len())]),
+            position: Position::Byte(0),
+            reason: "Binary images must start with the magic bytes 'INTC'.".to_string()
+        });
+    }
+
+    if bytes.len() < HEADER_LEN {
+        return Err(ParseError {
+            token: hex(&bytes[MAGIC.len()..]),
+            position: Position::Byte(MAGIC.len()),
+            reason: "Binary image header is truncated.".to_string()
+        });
+    }
+
+    let count = u32::from_le_bytes(bytes[4..HEADER_LEN].try_into().unwrap()) as usize;
+    let body = &bytes[HEADER_LEN..];
+
+    if body.len() != count * 4 {
+        let offset = HEADER_LEN + (body.len().min(count * 4) / 4) * 4;
+
+        return Err(ParseError {
+            token: hex(&bytes[offset..bytes.len().min(offset + 4)]),
+            position: Position::Byte(offset),
+            reason: format!("Header declares {} words but the body holds {} bytes.", count, body.len())
+        });
+    }
+
+    Ok(body.chunks(4).map(|word| i32::from_le_bytes(word.try_into().unwrap())).collect())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{detect, parse, parse_as, write, Format, ParseError, Position};
+
+    const PROGRAM: &[i32] = &[1002, 4, 3, 4, 33, -1, 0, 8, 99];
+
+    #[test]
+    fn round_trips_every_format() {
+        for format in &[Format::CommaSeparated, Format::LinePerWord, Format::Binary, Format::Annotated] {
+            let bytes = write(*format, PROGRAM);
+
+            assert_eq!(detect(&bytes), *format);
+            assert_eq!(parse(&bytes), Ok(PROGRAM.to_vec()));
+        }
+    }
+
+    #[test]
+    fn parses_annotated_text() {
+        let text = "# check if equal to 8\n3,9, # read\n2: 8,9 # compare\n\n6: 99";
+
+        assert_eq!(detect(text.as_bytes()), Format::Annotated);
+        assert_eq!(parse(text.as_bytes()), Ok(vec![3, 9, 8, 9, 0, 0, 99]));
+    }
+
+    #[test]
+    fn reports_the_offending_token() {
+        assert_eq!(parse(b"1,2,\n 3x,99"), Err(ParseError {
+            token: "3x".to_string(),
+            position: Position::Text { line: 2, column: 2 },
+            reason: "Failed to parse memory as i32: invalid digit found in string".to_string()
+        }));
+
+        let err = parse(b"0: 1,2\n1: 3").unwrap_err();
+        assert_eq!((err.token.as_str(), err.position), ("1", Position::Text { line: 2, column: 1 }));
+
+        let err = parse(b"# comment\n  5 : 1, 2, oops").unwrap_err();
+        assert_eq!((err.token.as_str(), err.position), ("oops", Position::Text { line: 2, column: 13 }));
+
+        assert_eq!(parse(b"4000000000: 99"), Err(ParseError {
+            token: "4000000000".to_string(),
+            position: Position::Text { line: 1, column: 1 },
+            reason: "Address is past the highest allowed, 1048576.".to_string()
+        }));
+    }
+
+    #[test]
+    fn reports_truncated_binary_images() {
+        let mut bytes = write(Format::Binary, &[1, 2, 3]);
+        bytes.truncate(bytes.len() - 2);
+
+        let err = parse(&bytes).unwrap_err();
+        assert_eq!((err.token.as_str(), err.position), ("0300", Position::Byte(16)));
+
+        let err = parse_as(Format::Binary, b"1,2,3").unwrap_err();
+        assert_eq!((err.token.as_str(), err.position), ("312c322c", Position::Byte(0)));
+    }
+}
@@ -0,0 +1,225 @@
+//! Counting how often each memory cell is read, written and executed, and
+//! drawing those counts as a heatmap, so that a program's code, its data and
+//! its scratch cells stand out from each other.
+
+use std::fmt::Write;
+
+use crate::computer::Computer;
+use crate::decoder::OpcodeWithParamModes;
+use crate::observer::Observer;
+
+const ANSI_RESET: &str = "\u{1b}[0m";
+
+/// How often one cell was used.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Counts {
+    /// Reads of the cell as a position mode param.
+    pub reads: u64,
+    pub writes: u64,
+    /// Instructions run that the cell was a word of.
+    pub executes: u64
+}
+
+/// Counts how often each cell is used, as an `Observer`.
+///
+/// Each cell is drawn with writes as red, reads as green and executes as
+/// blue, each scaled logarithmically against the busiest cell for that kind
+/// of use. Code comes out blue, data that is only read green, and scratch
+/// cells that are written and read back yellow. Cells never used are black.
+///
+/// ```
+/// use std::cell::RefCell;
+/// use std::rc::Rc;
+///
+/// use intcode::heatmap::Heatmap;
+/// use intcode::Computer;
+///
+/// let mut computer = Computer::new("3,9,1002,9,2,9,4,9,99,0", vec![21]).unwrap();
+/// let heatmap = Rc::new(RefCell::new(Heatmap::new(computer.memory().len())));
+/// computer.observe(Box::new(heatmap.clone()));
+/// computer.run().unwrap();
+///
+/// let counts = heatmap.borrow().counts(9);
+/// assert_eq!((counts.reads, counts.writes, counts.executes), (2, 2, 0));
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct Heatmap {
+    cells: Vec<Counts>
+}
+
+impl Heatmap {
+    /// Creates a heatmap of `len` cells, which grows if cells past the end
+    /// are used.
+    pub fn new(len: usize) -> Self {
+        Heatmap { cells: vec![Counts::default(); len] }
+    }
+
+    pub fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+
+    pub fn counts(&self, address: usize) -> Counts {
+        self.cells.get(address).copied().unwrap_or_default()
+    }
+
+    fn cell(&mut self, address: usize) -> &mut Counts {
+        if address >= self.cells.len() {
+            self.cells.resize(address + 1, Counts::default());
+        }
+
+        &mut self.cells[address]
+    }
+
+    fn executed(&mut self, pointer: usize, len: usize) {
+        for address in pointer..pointer + len {
+            self.cell(address).executes += 1;
+        }
+    }
+
+    /// The colour of each cell, as red, green and blue.
+    pub fn colours(&self) -> Vec<[u8; 3]> {
+        let max = self.cells.iter().fold(Counts::default(), |max, counts| Counts {
+            reads: max.reads.max(counts.reads),
+            writes: max.writes.max(counts.writes),
+            executes: max.executes.max(counts.executes)
+        });
+
+        let scale = |count: u64, max: u64| if count == 0 {
+            0
+        } else {
+            // Anything used at all is kept visible against the unused cells.
+            (64.0 + 191.0 * ((count + 1) as f64).ln() / ((max + 1) as f64).ln()).round() as u8
+        };
+
+        self.cells.iter()
+            .map(|counts| [scale(counts.writes, max.writes), scale(counts.reads, max.reads), scale(counts.executes, max.executes)])
+            .collect()
+    }
+
+    /// Renders the heatmap as a binary PPM image, `width` cells across and
+    /// with each cell drawn as a `scale` by `scale` square.
+    pub fn to_ppm(&self, width: usize, scale: usize) -> Vec<u8> {
+        let (width, scale) = (width.max(1), scale.max(1));
+        let colours = self.colours();
+        let rows = colours.len().div_ceil(width).max(1);
+
+        let mut ppm = format!("P6\n{} {}\n255\n", width * scale, rows * scale).into_bytes();
+
+        for row in 0..rows {
+            for _ in 0..scale {
+                for column in 0..width {
+                    let colour = colours.get(row * width + column).copied().unwrap_or([0; 3]);
+
+                    for _ in 0..scale {
+                        ppm.extend_from_slice(&colour);
+                    }
+                }
+            }
+        }
+
+        ppm
+    }
+
+    /// Renders the heatmap for a terminal, as rows of `width` cells coloured
+    /// with 24-bit ANSI escapes, with the address of the first cell of each
+    /// row in the margin.
+    pub fn to_ansi(&self, width: usize) -> String {
+        let width = width.max(1);
+        let colours = self.colours();
+        let address_width = self.len().saturating_sub(1).to_string().len();
+
+        let mut out = String::new();
+
+        for (row, cells) in colours.chunks(width).enumerate() {
+            write!(out, "{:>width$} ", row * width, width = address_width).unwrap();
+
+            for [red, green, blue] in cells {
+                write!(out, "\u{1b}[48;2;{};{};{}m  ", red, green, blue).unwrap();
+            }
+
+            out.push_str(ANSI_RESET);
+            out.push('\n');
+        }
+
+        out.push_str("Red: writes, green: reads, blue: executes.\n");
+        out
+    }
+}
+
+impl Observer for Heatmap {
+    fn after(&mut self, _computer: &Computer, pointer: usize, instruction: &OpcodeWithParamModes) {
+        self.executed(pointer, instruction.opcode.num_params() + 1);
+    }
+
+    fn halt(&mut self, computer: &Computer) {
+        self.executed(computer.pointer(), 1);
+    }
+
+    fn read(&mut self, address: usize, _value: i32) {
+        self.cell(address).reads += 1;
+    }
+
+    fn write(&mut self, address: usize, _value: i32) {
+        self.cell(address).writes += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::{Counts, Heatmap};
+    use crate::computer::Computer;
+
+    fn heatmap(program: &str, input: Vec<i32>) -> Heatmap {
+        let mut computer = Computer::new(program, input).unwrap();
+        let heatmap = Rc::new(RefCell::new(Heatmap::new(computer.memory().len())));
+        computer.observe(Box::new(heatmap.clone()));
+        computer.run().unwrap();
+
+        let heatmap = heatmap.borrow().clone();
+        heatmap
+    }
+
+    #[test]
+    fn counts_reads_writes_and_executes() {
+        // Counts down from the input to 0, outputting each value, with the
+        // counter at 12 and a constant at 13.
+        let heatmap = heatmap("3,12,4,12,1,12,13,12,1005,12,2,99,0,-1", vec![3]);
+
+        assert_eq!(heatmap.counts(0), Counts { reads: 0, writes: 0, executes: 1 });
+        assert_eq!(heatmap.counts(2), Counts { reads: 0, writes: 0, executes: 3 });
+        assert_eq!(heatmap.counts(11), Counts { reads: 0, writes: 0, executes: 1 });
+        assert_eq!(heatmap.counts(12), Counts { reads: 9, writes: 4, executes: 0 });
+        assert_eq!(heatmap.counts(13), Counts { reads: 3, writes: 0, executes: 0 });
+        assert_eq!(heatmap.counts(100), Counts::default());
+
+        let colours = heatmap.colours();
+        assert_eq!(colours[0], [0, 0, 160]);
+        assert_eq!(colours[12], [255, 255, 0]);
+        assert_eq!(colours[13], [0, 179, 0]);
+    }
+
+    #[test]
+    fn renders_ppm_and_ansi() {
+        let heatmap = heatmap("1101,2,3,5,99,0", vec![]);
+
+        let ppm = heatmap.to_ppm(4, 2);
+        let header = b"P6\n8 4\n255\n";
+        assert_eq!(&ppm[..header.len()], header);
+        assert_eq!(ppm.len(), header.len() + 8 * 4 * 3);
+        // The two rows of pixels for the first row of cells repeat.
+        assert_eq!(ppm[header.len()..header.len() + 24], ppm[header.len() + 24..header.len() + 48]);
+
+        assert_eq!(heatmap.to_ansi(4), "\
+0 \u{1b}[48;2;0;0;255m  \u{1b}[48;2;0;0;255m  \u{1b}[48;2;0;0;255m  \u{1b}[48;2;0;0;255m  \u{1b}[0m
+4 \u{1b}[48;2;0;0;255m  \u{1b}[48;2;255;0;0m  \u{1b}[0m
+Red: writes, green: reads, blue: executes.
+");
+    }
+}
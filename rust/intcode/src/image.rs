@@ -0,0 +1,185 @@
+//! Sharing one parsed program between many computers, copying only the parts
+//! each of them writes to.
+
+use std::ops::Index;
+use std::sync::Arc;
+
+use crate::error::Error;
+use crate::format::{self, Format};
+
+/// How many words `Memory` copies at a time, the first time one of them is
+/// written to.
+pub const PAGE_SIZE: usize = 256;
+
+/// A parsed program that never changes, and so can be shared between any
+/// number of computers, and threads, without being copied.
+///
+/// Cloning an image is cheap, as clones share the same words.
+///
+/// ```
+/// use intcode::image::Image;
+/// use intcode::Computer;
+///
+/// let image = Image::parse("1002,4,3,4,33").unwrap();
+///
+/// for _ in 0..3 {
+///     let mut computer = Computer::with_image(image.clone(), vec![]);
+///     computer.run().unwrap();
+///     assert_eq!(computer.memory()[4], 99);
+/// }
+///
+/// assert_eq!(image.words()[4], 33);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    words: Arc<[i32]>
+}
+
+impl Image {
+    pub fn new(words: Vec<i32>) -> Self {
+        Image { words: words.into() }
+    }
+
+    /// Parses a comma-separated program, eg. `1,0,0,0,99`.
+    pub fn parse(raw_memory: &str) -> Result<Self, Error> {
+        Ok(Image::new(format::parse_as(Format::CommaSeparated, raw_memory.as_bytes())?))
+    }
+
+    /// Parses a program image in any of the formats in `format`, detecting
+    /// which one it is.
+    pub fn from_bytes(image: &[u8]) -> Result<Self, Error> {
+        Ok(Image::new(format::parse(image)?))
+    }
+
+    pub fn words(&self) -> &[i32] {
+        &self.words
+    }
+}
+
+/// The memory of a `Computer`: an `Image`, plus a copy of each page of it
+/// that has been written to.
+///
+/// Reads of a page that has never been written go straight to the image, so
+/// a new computer costs one entry per page until it writes to memory.
+#[derive(Debug, Clone)]
+pub struct Memory {
+    image: Image,
+    pages: Vec<Option<Box<[i32]>>>
+}
+
+impl Memory {
+    pub fn new(image: Image) -> Self {
+        let pages = image.words.len().div_ceil(PAGE_SIZE);
+        Memory { image, pages: vec![None; pages] }
+    }
+
+    /// The image this memory started as.
+    pub fn image(&self) -> &Image {
+        &self.image
+    }
+
+    pub fn len(&self) -> usize {
+        self.image.words.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, address: usize) -> Option<&i32> {
+        let page = address / PAGE_SIZE;
+
+        match self.pages.get(page)? {
+            Some(copy) => copy.get(address % PAGE_SIZE),
+            None => self.image.words.get(address)
+        }
+    }
+
+    /// Writes `value` to `address`, copying its page first if this is the
+    /// first write to it. Returns `None` if `address` is out of range.
+    pub fn set(&mut self, address: usize, value: i32) -> Option<()> {
+        let page = address / PAGE_SIZE;
+        let words = &self.image.words;
+        let copy = self.pages.get_mut(page)?.get_or_insert_with(|| {
+            let start = page * PAGE_SIZE;
+            words[start..(start + PAGE_SIZE).min(words.len())].into()
+        });
+
+        *copy.get_mut(address % PAGE_SIZE)? = value;
+        Some(())
+    }
+
+    /// How many pages have been copied so far.
+    pub fn copied_pages(&self) -> usize {
+        self.pages.iter().filter(|page| page.is_some()).count()
+    }
+
+    /// Throws away every write, leaving memory as the image again.
+    pub fn reset(&mut self) {
+        for page in self.pages.iter_mut() {
+            *page = None;
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &i32> + '_ {
+        (0..self.len()).map(move |address| &self[address])
+    }
+
+    pub fn to_vec(&self) -> Vec<i32> {
+        self.iter().copied().collect()
+    }
+}
+
+impl Index<usize> for Memory {
+    type Output = i32;
+
+    fn index(&self, address: usize) -> &i32 {
+        self.get(address).unwrap_or_else(|| panic!("Address {} is out of range for memory of {} words.", address, self.len()))
+    }
+}
+
+impl PartialEq for Memory {
+    fn eq(&self, other: &Memory) -> bool {
+        self.iter().eq(other.iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Image, Memory, PAGE_SIZE};
+    use crate::computer::Computer;
+
+    #[test]
+    fn copies_pages_on_write() {
+        let image = Image::new((0..1000).collect());
+        let mut memory = Memory::new(image.clone());
+        assert_eq!(memory.copied_pages(), 0);
+
+        assert_eq!(memory.set(PAGE_SIZE + 1, -1), Some(()));
+        assert_eq!(memory.set(999, -2), Some(()));
+        assert_eq!(memory.set(1000, -3), None);
+        assert_eq!(memory.copied_pages(), 2);
+
+        assert_eq!((memory[PAGE_SIZE], memory[PAGE_SIZE + 1], memory[999]), (PAGE_SIZE as i32, -1, -2));
+        assert_eq!(memory.get(1000), None);
+        assert_eq!(image.words()[999], 999);
+
+        memory.reset();
+        assert_eq!(memory.copied_pages(), 0);
+        assert_eq!(memory, Memory::new(image.clone()));
+        assert_eq!(memory.to_vec(), image.words().to_vec());
+    }
+
+    #[test]
+    fn resets_a_computer_to_its_image() {
+        let image = Image::parse("3,9,1002,9,2,9,4,9,99,0").unwrap();
+        let mut computer = Computer::with_image(image, vec![21]);
+        assert_eq!(computer.exec(), Some(42));
+
+        computer.reset(vec![5]);
+        assert_eq!(computer.memory()[9], 0);
+        assert_eq!((computer.pointer(), computer.outputs()), (0, &[][..]));
+        assert_eq!(computer.exec(), Some(10));
+        assert_eq!(computer.timing().instructions, 4);
+    }
+}
@@ -0,0 +1,43 @@
+//! A shared Intcode interpreter, as first needed by days 2 and 5.
+//!
+//! A program is loaded into a [`Computer`](computer/struct.Computer.html),
+//! either from the comma-separated puzzle input with `Computer::new` or from
+//! any of the image formats in [`format`](format/index.html). Each
+//! instruction is decoded by [`decoder`](decoder/index.html) and anything
+//! that goes wrong is reported as an [`Error`](error/enum.Error.html).
+//!
+//! ```
+//! use intcode::Computer;
+//!
+//! let mut computer = Computer::new("3,0,4,0,99", vec![42]).unwrap();
+//! assert_eq!(computer.exec(), Some(42));
+//! ```
+
+pub mod compile;
+pub mod computer;
+pub mod coverage;
+pub mod decoder;
+pub mod decompile;
+pub mod device;
+pub mod diff;
+pub mod equivalence;
+pub mod error;
+pub mod explain;
+pub mod format;
+pub mod heatmap;
+pub mod image;
+pub mod minimize;
+pub mod observer;
+pub mod optimize;
+pub mod protection;
+pub mod scheduler;
+pub mod search;
+pub mod session;
+pub mod spec;
+pub mod sweep;
+pub mod timing;
+pub mod trace;
+
+pub use computer::{Computer, Status};
+pub use decoder::{Arithmetic, Decoding, Opcode, OpcodeWithParamModes, ParamMode};
+pub use error::Error;
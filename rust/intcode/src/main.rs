@@ -0,0 +1,449 @@
+//! The `intcode` command line tool, for running, tracing and disassembling
+//! programs. Run it with no arguments to see how to use it.
+
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::process;
+use std::rc::Rc;
+
+use intcode::explain::Explainer;
+use intcode::format::{self, Format};
+use intcode::heatmap::Heatmap;
+use intcode::observer::{Control, Observer};
+use intcode::trace::{self, Tracer};
+use intcode::{Computer, Error, OpcodeWithParamModes, Status};
+
+const USAGE: &str = "\
+Usage: intcode <command> <file> [options]
+
+Commands:
+  run <file>            Run the program, printing each output on its own line.
+  trace <file>          Run the program, printing each instruction as it runs.
+  explain <file>        Step through the program, explaining each instruction.
+                        Press enter to step, `c` to run to the end or `q` to
+                        quit.
+  disasm <file>         Disassemble the program from address 0.
+  dump-memory <file>    Run the program, then print its memory.
+  heatmap <file>        Run the program, then draw how often each cell was
+                        read, written and executed.
+  patch <file> A=V...   Print the program with the word at each address A set
+                        to V.
+
+Options:
+  --input 1,5           Values to input, comma-separated.
+  --patch A=V           Set the word at address A to V before running, eg.
+                        `--patch 1=12 --patch 2=2` for day 2. May be repeated.
+  --budget N            Stop after N instructions.
+  --chrome              With `trace`, print Chrome Trace Event JSON instead.
+  --ppm                 With `heatmap`, print a PPM image instead.
+  --width N             With `heatmap`, draw N cells per row (default 32).
+  --format F            With `dump-memory` and `patch`, print memory as
+                        `comma` (the default), `lines`, `annotated` or
+                        `binary`.
+
+The file may be in any of the formats the library reads.
+
+Exit codes:
+  0  The program halted.
+  1  The program failed.
+  2  The command line or the file was invalid.
+  3  The program used up its budget.
+  4  The program needed more input than it was given.
+  5  `explain` was quit, or its input ended, before the program stopped.
+";
+
+const HALTED: i32 = 0;
+const FAILED: i32 = 1;
+const INVALID: i32 = 2;
+const OUT_OF_BUDGET: i32 = 3;
+const AWAITING_INPUT: i32 = 4;
+const QUIT: i32 = 5;
+
+// Prints a line like `println!`, but through `written`, so that the command
+// stops quietly once whoever reads stdout has gone.
+macro_rules! out {
+    ($($arg:tt)*) => {
+        written(writeln!(io::stdout().lock(), $($arg)*))
+    };
+}
+
+struct Options {
+    command: String,
+    file: String,
+    input: Vec<i32>,
+    patches: Vec<(usize, i32)>,
+    budget: Option<usize>,
+    chrome: bool,
+    ppm: bool,
+    width: usize,
+    format: Format
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = parse_args(&args).unwrap_or_else(|err| invalid(&err));
+
+    let image = fs::read(&options.file).unwrap_or_else(|err| invalid(&format!("Could not read {}: {}", options.file, err)));
+    let memory = format::parse(&image).unwrap_or_else(|err| invalid(&format!("Could not parse {}: {}", options.file, err)));
+    let computer = Computer::with_memory(memory, options.input.clone())
+        .with_patches(&options.patches)
+        .unwrap_or_else(|err| invalid(&err.to_string()));
+
+    let code = match options.command.as_str() {
+        "run" => run_command(computer, &options),
+        "trace" => trace_command(computer, &options),
+        "explain" => explain_command(computer, options.budget),
+        "disasm" => disasm_command(&computer),
+        "dump-memory" => dump_memory_command(computer, &options),
+        "heatmap" => heatmap_command(computer, &options),
+        "patch" => {
+            write_memory(&computer.memory().to_vec(), options.format);
+            HALTED
+        },
+        _ => unreachable!()
+    };
+
+    process::exit(code);
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let command = match args.first() {
+        Some(command) if ["run", "trace", "explain", "disasm", "dump-memory", "heatmap", "patch"].contains(&command.as_str()) => command.clone(),
+        Some(command) => return Err(format!("Unknown command '{}'.", command)),
+        None => return Err("Expected a command.".to_string())
+    };
+    let file = args.get(1).filter(|file| !file.starts_with("--")).cloned()
+        .ok_or_else(|| "Expected a file.".to_string())?;
+
+    let mut options = Options {
+        command,
+        file,
+        input: Vec::new(),
+        patches: Vec::new(),
+        budget: None,
+        chrome: false,
+        ppm: false,
+        width: 32,
+        format: Format::CommaSeparated
+    };
+
+    let mut rest = args[2..].iter();
+
+    while let Some(arg) = rest.next() {
+        let mut value = || rest.next().ok_or_else(|| format!("Expected a value after '{}'.", arg));
+
+        match arg.as_str() {
+            "--input" => {
+                options.input = value()?.split(',')
+                    .map(str::trim)
+                    .filter(|value| !value.is_empty())
+                    .map(|value| value.parse().map_err(|err| format!("Failed to parse '{}' as i32: {}", value, err)))
+                    .collect::<Result<_, _>>()?;
+            },
+            "--patch" => options.patches.push(parse_patch(value()?)?),
+            "--budget" => {
+                let budget = value()?;
+                options.budget = Some(budget.parse().map_err(|err| format!("Failed to parse budget '{}': {}", budget, err))?);
+            },
+            "--chrome" => options.chrome = true,
+            "--ppm" => options.ppm = true,
+            "--width" => {
+                let width = value()?;
+                options.width = width.parse().ok().filter(|&width| width > 0)
+                    .ok_or_else(|| format!("Expected a width above 0 but got '{}'.", width))?;
+            },
+            "--format" => {
+                options.format = match value()?.as_str() {
+                    "comma" => Format::CommaSeparated,
+                    "lines" => Format::LinePerWord,
+                    "annotated" => Format::Annotated,
+                    "binary" => Format::Binary,
+                    other => return Err(format!("Unknown format '{}'.", other))
+                };
+            },
+            patch if options.command == "patch" && !patch.starts_with("--") => options.patches.push(parse_patch(patch)?),
+            other => return Err(format!("Unknown option '{}'.", other))
+        }
+    }
+
+    Ok(options)
+}
+
+fn parse_patch(patch: &str) -> Result<(usize, i32), String> {
+    let invalid = || format!("Expected a patch like '1=12' but got '{}'.", patch);
+    let (address, value) = patch.split_once('=').ok_or_else(invalid)?;
+
+    Ok((address.trim().parse().map_err(|_| invalid())?, value.trim().parse().map_err(|_| invalid())?))
+}
+
+// Exits quietly if stdout was closed early, eg. when piped into `head`, as
+// there is no one left to read the rest.
+fn written(result: io::Result<()>) {
+    match result {
+        Ok(()) => {},
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => process::exit(HALTED),
+        Err(err) => {
+            eprintln!("Could not write to stdout: {}", err);
+            process::exit(FAILED);
+        }
+    }
+}
+
+fn invalid(reason: &str) -> ! {
+    eprintln!("{}\n\n{}", reason, USAGE);
+    process::exit(INVALID);
+}
+
+// Runs the computer to the end, or until the budget is used up, returning
+// the exit code for how it stopped.
+fn execute(computer: &mut Computer, budget: Option<usize>) -> i32 {
+    let result = run_to_end(computer, budget);
+    exit_code(computer, result)
+}
+
+fn run_to_end(computer: &mut Computer, budget: Option<usize>) -> Result<Status, Error> {
+    match budget {
+        Some(budget) => computer.run_for(budget),
+        None => computer.run()
+    }
+}
+
+// Reports how the computer stopped, returning the exit code for it.
+fn exit_code(computer: &Computer, result: Result<Status, Error>) -> i32 {
+    match result {
+        Ok(Status::Halted) => HALTED,
+        Ok(Status::AwaitingInput) => {
+            eprintln!("The program needs more input, at {}.", computer.pointer());
+            AWAITING_INPUT
+        },
+        Ok(_) => {
+            eprintln!("The program used up its budget, at {}.", computer.pointer());
+            OUT_OF_BUDGET
+        },
+        Err(err) => {
+            eprintln!("{}", err);
+            FAILED
+        }
+    }
+}
+
+fn run_command(mut computer: Computer, options: &Options) -> i32 {
+    let code = execute(&mut computer, options.budget);
+
+    for output in computer.outputs() {
+        out!("{}", output);
+    }
+
+    code
+}
+
+// Prints each instruction as it runs, and each value it outputs. Each
+// instruction is printed once its params have been fetched, so that they are
+// shown as it saw them.
+#[derive(Debug, Default)]
+struct Printer {
+    fetching: Option<(usize, OpcodeWithParamModes, Vec<i32>)>
+}
+
+impl Printer {
+    fn flush(&mut self) {
+        if let Some((pointer, instruction, words)) = self.fetching.take() {
+            out!("{:>6}  {}", pointer, instruction.disassemble(&words, 0));
+        }
+    }
+}
+
+impl Observer for Printer {
+    fn before(&mut self, computer: &Computer, instruction: &OpcodeWithParamModes) -> Control {
+        // An input instruction that waited for a value is fetched again.
+        self.flush();
+        // The opcode itself has been fetched already, and isn't needed.
+        self.fetching = Some((computer.pointer(), instruction.clone(), vec![0]));
+        Control::Continue
+    }
+
+    fn fetch(&mut self, address: usize, value: i32) {
+        if let Some((pointer, instruction, words)) = &mut self.fetching {
+            if words.len() <= instruction.opcode.num_params() && address == *pointer + words.len() {
+                words.push(value);
+            }
+        }
+    }
+
+    fn after(&mut self, _computer: &Computer, _pointer: usize, _instruction: &OpcodeWithParamModes) {
+        self.flush();
+    }
+
+    fn output(&mut self, value: i32) {
+        self.flush();
+        out!("{:>6}  => {}", "", value);
+    }
+
+    fn halt(&mut self, _computer: &Computer) {
+        self.flush();
+    }
+}
+
+fn trace_command(mut computer: Computer, options: &Options) -> i32 {
+    if !options.chrome {
+        let printer = Rc::new(RefCell::new(Printer::default()));
+        computer.observe(Box::new(printer.clone()));
+        let result = run_to_end(&mut computer, options.budget);
+
+        // Shows the instruction that failed, or is waiting for input, before
+        // saying why.
+        printer.borrow_mut().flush();
+        return exit_code(&computer, result);
+    }
+
+    let tracer = Rc::new(RefCell::new(Tracer::new(0, &options.file)));
+    computer.observe(Box::new(tracer.clone()));
+    let code = execute(&mut computer, options.budget);
+
+    written(write!(io::stdout().lock(), "{}", trace::to_json(&[&tracer.borrow()])));
+    code
+}
+
+fn explain_command(mut computer: Computer, budget: Option<usize>) -> i32 {
+    let explainer = Rc::new(RefCell::new(Explainer::default()));
+    computer.observe(Box::new(explainer.clone()));
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut stepping = true;
+    let mut steps = 0;
+
+    loop {
+        if budget.is_some_and(|budget| steps >= budget) {
+            return exit_code(&computer, Ok(Status::Running));
+        }
+
+        let status = match computer.run_for(1) {
+            Ok(status) => status,
+            Err(err) => return exit_code(&computer, Err(err))
+        };
+
+        // An input instruction waiting for a value hasn't run yet.
+        if status != Status::AwaitingInput {
+            steps += 1;
+        }
+
+        for line in explainer.borrow_mut().lines.drain(..) {
+            out!("{}", line);
+        }
+
+        match status {
+            Status::Halted => return HALTED,
+            Status::AwaitingInput => {
+                let mut stdout = io::stdout().lock();
+                written(write!(stdout, "input> ").and_then(|()| stdout.flush()));
+
+                match lines.next().map(|line| line.map(|line| line.trim().parse())) {
+                    Some(Ok(Ok(value))) => computer.push_input(value),
+                    _ => {
+                        eprintln!("Expected a number to input.");
+                        return AWAITING_INPUT;
+                    }
+                }
+            },
+            _ if stepping => {
+                match lines.next() {
+                    Some(Ok(ref command)) if command.trim() == "c" => stepping = false,
+                    Some(Ok(ref command)) if command.trim() == "q" => return QUIT,
+                    Some(Ok(_)) => {},
+                    _ => return QUIT
+                }
+            },
+            _ => {}
+        }
+    }
+}
+
+fn disasm_command(computer: &Computer) -> i32 {
+    let memory = computer.memory().to_vec();
+    let mut address = 0;
+
+    while address < memory.len() {
+        let len = OpcodeWithParamModes::try_from(memory[address]).ok()
+            .map(|decoded| (decoded.opcode.num_params() + 1, decoded))
+            .filter(|(len, _)| address + len <= memory.len());
+
+        match len {
+            Some((len, decoded)) => {
+                out!("{:>6}  {}", address, decoded.disassemble(&memory, address));
+                address += len;
+            },
+            None => {
+                out!("{:>6}  {}", address, memory[address]);
+                address += 1;
+            }
+        }
+    }
+
+    HALTED
+}
+
+fn dump_memory_command(mut computer: Computer, options: &Options) -> i32 {
+    let code = execute(&mut computer, options.budget);
+    write_memory(&computer.memory().to_vec(), options.format);
+    code
+}
+
+fn heatmap_command(mut computer: Computer, options: &Options) -> i32 {
+    let heatmap = Rc::new(RefCell::new(Heatmap::new(computer.memory().len())));
+    computer.observe(Box::new(heatmap.clone()));
+    let code = execute(&mut computer, options.budget);
+
+    let heatmap = heatmap.borrow();
+    if options.ppm {
+        written(io::stdout().lock().write_all(&heatmap.to_ppm(options.width, 8)));
+    } else {
+        written(write!(io::stdout().lock(), "{}", heatmap.to_ansi(options.width)));
+    }
+
+    code
+}
+
+fn write_memory(memory: &[i32], format: Format) {
+    written(io::stdout().lock().write_all(&format::write(format, memory)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_args, parse_patch};
+    use intcode::format::Format;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn parses_options() {
+        let options = parse_args(&args("run day-2.txt --input 1,5 --patch 1=12 --patch 2=2 --budget 100")).unwrap();
+        assert_eq!((options.command.as_str(), options.file.as_str()), ("run", "day-2.txt"));
+        assert_eq!(options.input, vec![1, 5]);
+        assert_eq!(options.patches, vec![(1, 12), (2, 2)]);
+        assert_eq!(options.budget, Some(100));
+
+        let options = parse_args(&args("patch day-2.txt 1=12 2=2 --format annotated")).unwrap();
+        assert_eq!(options.patches, vec![(1, 12), (2, 2)]);
+        assert_eq!(options.format, Format::Annotated);
+
+        let options = parse_args(&args("heatmap day-5.txt --input 1 --ppm --width 64")).unwrap();
+        assert_eq!((options.ppm, options.width), (true, 64));
+    }
+
+    #[test]
+    fn rejects_invalid_arguments() {
+        assert_eq!(parse_args(&args("")).err(), Some("Expected a command.".to_string()));
+        assert_eq!(parse_args(&args("jump x.txt")).err(), Some("Unknown command 'jump'.".to_string()));
+        assert_eq!(parse_args(&args("run --input 1")).err(), Some("Expected a file.".to_string()));
+        assert_eq!(parse_args(&args("run x.txt 1=12")).err(), Some("Unknown option '1=12'.".to_string()));
+        assert_eq!(parse_args(&args("run x.txt --budget")).err(), Some("Expected a value after '--budget'.".to_string()));
+        assert_eq!(parse_args(&args("heatmap x.txt --width 0")).err(), Some("Expected a width above 0 but got '0'.".to_string()));
+        assert_eq!(parse_patch("1:12"), Err("Expected a patch like '1=12' but got '1:12'.".to_string()));
+    }
+}
@@ -0,0 +1,195 @@
+//! Shrinking a program that shows up a bug down to one small enough to debug.
+
+use std::convert::TryFrom;
+use std::fmt;
+
+use crate::decoder::{Opcode, OpcodeWithParamModes};
+use crate::format::{self, Format};
+
+/// The result of `minimize`.
+///
+/// Displays as the comma-separated puzzle input format, ready to paste into a
+/// regression test.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Minimized {
+    pub memory: Vec<i32>,
+    /// How many times the predicate was called.
+    pub runs: usize
+}
+
+impl fmt::Display for Minimized {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = format::write(Format::CommaSeparated, &self.memory);
+        write!(f, "{}", String::from_utf8_lossy(&text).trim_end())
+    }
+}
+
+/// Shrinks `memory` to a program for which `fails` still returns true.
+///
+/// This repeats, until none of them help:
+///
+/// * Cutting words off the end, halving how many are cut each time one cut
+///   stops the program failing.
+/// * Replacing instructions with no-ops, first all of them together and then
+///   in smaller and smaller blocks. A no-op is an unconditional jump to the
+///   next instruction, so it needs at least three words.
+/// * Replacing single instructions with `Halt`.
+///
+/// Instructions are found by decoding each word in turn from address 0, as
+/// a disassembler would. If `memory` does not fail to begin with it is
+/// returned as it is.
+///
+/// The predicate decides what failing means. Candidates can easily loop
+/// forever, eg. once an instruction that leaves a loop is replaced, so the
+/// predicate must bound its runs, eg. with `Computer::run_for`. To look for a
+/// panic, wrap the run in `std::panic::catch_unwind`.
+pub fn minimize<P>(memory: &[i32], mut fails: P) -> Minimized
+    where P: FnMut(&[i32]) -> bool {
+
+    let mut runs = 0;
+    let mut fails = |memory: &[i32]| {
+        runs += 1;
+        fails(memory)
+    };
+
+    let mut memory = memory.to_vec();
+    if !fails(&memory) {
+        return Minimized { memory, runs };
+    }
+
+    loop {
+        let before = memory.clone();
+
+        truncate(&mut memory, &mut fails);
+        replace_with_noops(&mut memory, &mut fails);
+        replace_with_halts(&mut memory, &mut fails);
+
+        if memory == before {
+            break;
+        }
+    }
+
+    Minimized { memory, runs }
+}
+
+fn truncate<P: FnMut(&[i32]) -> bool>(memory: &mut Vec<i32>, fails: &mut P) {
+    let mut cut = memory.len() / 2;
+
+    while cut > 0 {
+        if cut <= memory.len() && fails(&memory[..memory.len() - cut]) {
+            memory.truncate(memory.len() - cut);
+        } else {
+            cut /= 2;
+        }
+    }
+}
+
+fn replace_with_noops<P: FnMut(&[i32]) -> bool>(memory: &mut Vec<i32>, fails: &mut P) {
+    // No-ops keep every instruction where it was, so these stay valid.
+    let insns: Vec<(usize, usize)> = instructions(memory).into_iter()
+        .filter(|&(address, len)| len >= 3 && !is_noop(memory, address, len))
+        .collect();
+    let mut block = insns.len();
+
+    while block > 0 {
+        for chunk in insns.chunks(block) {
+            let mut candidate = memory.clone();
+            for &(address, len) in chunk {
+                candidate[address] = 1105;
+                candidate[address + 1] = 1;
+                candidate[address + 2] = (address + len) as i32;
+                for word in &mut candidate[address + 3..address + len] {
+                    *word = 0;
+                }
+            }
+
+            if candidate != *memory && fails(&candidate) {
+                *memory = candidate;
+            }
+        }
+
+        block /= 2;
+    }
+}
+
+fn replace_with_halts<P: FnMut(&[i32]) -> bool>(memory: &mut Vec<i32>, fails: &mut P) {
+    for (address, _) in instructions(memory) {
+        if memory[address] == Opcode::Halt as i32 {
+            continue;
+        }
+
+        let mut candidate = memory.clone();
+        candidate[address] = Opcode::Halt as i32;
+
+        if fails(&candidate) {
+            *memory = candidate;
+            // Everything after a halt is now data, so boundaries may differ.
+            return;
+        }
+    }
+}
+
+// The address and length of each instruction, decoding from address 0.
+// Words that are not instructions are skipped over one at a time.
+pub(crate) fn instructions(memory: &[i32]) -> Vec<(usize, usize)> {
+    let mut insns = Vec::new();
+    let mut address = 0;
+
+    while address < memory.len() {
+        let len = OpcodeWithParamModes::try_from(memory[address]).ok()
+            .map(|decoded| decoded.opcode.num_params() + 1)
+            .filter(|len| address + len <= memory.len());
+
+        match len {
+            Some(len) => {
+                insns.push((address, len));
+                address += len;
+            },
+            None => address += 1
+        }
+    }
+
+    insns
+}
+
+fn is_noop(memory: &[i32], address: usize, len: usize) -> bool {
+    memory[address..address + 3] == [1105, 1, (address + len) as i32]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::minimize;
+    use crate::computer::Computer;
+
+    fn outputs(memory: &[i32], input: Vec<i32>) -> Vec<i32> {
+        let mut computer = Computer::with_memory(memory.to_vec(), input);
+        let _ = computer.run_for(1_000);
+        computer.outputs().to_vec()
+    }
+
+    #[test]
+    fn shrinks_small_programs() {
+        let program = [1101, 2, 3, 15, 1102, 4, 5, 16, 104, 5, 1101, 6, 7, 17, 99, 0, 0, 0];
+        let minimized = minimize(&program, |memory| outputs(memory, vec![]).first() == Some(&5));
+
+        assert_eq!(minimized.memory, vec![1105, 1, 4, 0, 1105, 1, 8, 0, 104, 5]);
+        assert_eq!(minimized.to_string(), "1105,1,4,0,1105,1,8,0,104,5");
+
+        let passing = minimize(&program, |_| false);
+        assert_eq!(passing.memory, program.to_vec());
+        assert_eq!(passing.runs, 1);
+    }
+
+    #[test]
+    fn shrinks_until_nothing_more_can_go() {
+        // Fails when the program outputs anything at all after its first
+        // output, like a bug in how outputs are queued might.
+        let program = [104, 1, 1101, 1, 2, 16, 104, 2, 1105, 1, 13, 104, 3, 104, 4, 99, 0];
+        let fails = |memory: &[i32]| outputs(memory, vec![]).len() > 1;
+        let minimized = minimize(&program, fails);
+
+        assert!(fails(&minimized.memory));
+        assert_eq!(minimized.memory, vec![104, 1, 1105, 1, 6, 0, 104, 2]);
+        assert_eq!(minimize(&minimized.memory, fails).memory, minimized.memory);
+    }
+}
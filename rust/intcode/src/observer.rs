@@ -0,0 +1,211 @@
+//! Hooks for watching, and stopping, a `Computer` as it runs.
+
+use std::cell::RefCell;
+use std::fmt::Debug;
+use std::rc::Rc;
+
+use crate::computer::Computer;
+use crate::decoder::OpcodeWithParamModes;
+
+/// What an `Observer` wants the computer to do next.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Control {
+    Continue,
+    /// Stop before the instruction runs, with `Status::Breakpoint`.
+    Break
+}
+
+/// Something that is told about everything a `Computer` does, see
+/// `Computer::observe`.
+///
+/// Every observer attached is called, in the order they were attached, for
+/// each event:
+///
+/// * `fetch` is called for every instruction word as it is fetched, the
+///   opcode first and then each raw param, including words that come from a
+///   device. The opcode is fetched before `before` is called.
+/// * `before` is called once an instruction has been fetched and decoded,
+///   before it reads any params. If any observer returns `Control::Break`
+///   the instruction is not run. When the computer is run again it is
+///   offered to `before` again, and any `Control::Break` is then ignored so
+///   that it can carry on.
+/// * `read` is called for every param read in position mode, and `write` for
+///   every value written to memory, whether to memory or to a device.
+///   Instruction words are not reported to `read`, as `before` covers those.
+/// * `input` and `output` are called with each value as it is consumed or
+///   produced.
+/// * `after` is called once the instruction has retired, with the pointer
+///   already moved on to the next one. An input instruction that has to wait
+///   for a value does not retire, and is offered to `before` again when it is
+///   retried.
+/// * `halt` is called when a halt instruction is executed, instead of
+///   `after`.
+///
+/// Every method does nothing by default, so observers only implement the
+/// ones they are interested in.
+pub trait Observer: Debug {
+    fn before(&mut self, _computer: &Computer, _instruction: &OpcodeWithParamModes) -> Control {
+        Control::Continue
+    }
+
+    fn after(&mut self, _computer: &Computer, _pointer: usize, _instruction: &OpcodeWithParamModes) {}
+    fn fetch(&mut self, _address: usize, _value: i32) {}
+    fn read(&mut self, _address: usize, _value: i32) {}
+    fn write(&mut self, _address: usize, _value: i32) {}
+    fn input(&mut self, _value: i32) {}
+    fn output(&mut self, _value: i32) {}
+    fn halt(&mut self, _computer: &Computer) {}
+}
+
+/// Lets an observer be attached while a handle to it is kept, so that what
+/// it collected can be looked at once the computer has run.
+impl<T: Observer> Observer for Rc<RefCell<T>> {
+    fn before(&mut self, computer: &Computer, instruction: &OpcodeWithParamModes) -> Control {
+        self.borrow_mut().before(computer, instruction)
+    }
+
+    fn after(&mut self, computer: &Computer, pointer: usize, instruction: &OpcodeWithParamModes) {
+        self.borrow_mut().after(computer, pointer, instruction)
+    }
+
+    fn fetch(&mut self, address: usize, value: i32) {
+        self.borrow_mut().fetch(address, value)
+    }
+
+    fn read(&mut self, address: usize, value: i32) {
+        self.borrow_mut().read(address, value)
+    }
+
+    fn write(&mut self, address: usize, value: i32) {
+        self.borrow_mut().write(address, value)
+    }
+
+    fn input(&mut self, value: i32) {
+        self.borrow_mut().input(value)
+    }
+
+    fn output(&mut self, value: i32) {
+        self.borrow_mut().output(value)
+    }
+
+    fn halt(&mut self, computer: &Computer) {
+        self.borrow_mut().halt(computer)
+    }
+}
+
+/// Stops the computer before executing any of a set of addresses.
+#[derive(Debug, Clone, Default)]
+pub struct Breakpoints {
+    addresses: Vec<usize>
+}
+
+impl Breakpoints {
+    pub fn new(addresses: &[usize]) -> Self {
+        Breakpoints { addresses: addresses.to_vec() }
+    }
+}
+
+impl Observer for Breakpoints {
+    fn before(&mut self, computer: &Computer, _instruction: &OpcodeWithParamModes) -> Control {
+        if self.addresses.contains(&computer.pointer()) {
+            Control::Break
+        } else {
+            Control::Continue
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::{Breakpoints, Control, Observer};
+    use crate::computer::{Computer, Status};
+    use crate::decoder::OpcodeWithParamModes;
+
+    #[derive(Debug, Default)]
+    struct Log {
+        events: Vec<String>
+    }
+
+    impl Observer for Log {
+        fn before(&mut self, computer: &Computer, instruction: &OpcodeWithParamModes) -> Control {
+            self.events.push(format!("before {}", instruction.disassemble(&computer.memory().to_vec(), computer.pointer())));
+            Control::Continue
+        }
+
+        fn after(&mut self, computer: &Computer, pointer: usize, _instruction: &OpcodeWithParamModes) {
+            self.events.push(format!("after {} -> {}", pointer, computer.pointer()));
+        }
+
+        fn read(&mut self, address: usize, value: i32) {
+            self.events.push(format!("read [{}] = {}", address, value));
+        }
+
+        fn write(&mut self, address: usize, value: i32) {
+            self.events.push(format!("write [{}] = {}", address, value));
+        }
+
+        fn input(&mut self, value: i32) {
+            self.events.push(format!("input {}", value));
+        }
+
+        fn output(&mut self, value: i32) {
+            self.events.push(format!("output {}", value));
+        }
+
+        fn halt(&mut self, computer: &Computer) {
+            self.events.push(format!("halt {}", computer.pointer()));
+        }
+    }
+
+    #[test]
+    fn observers_see_every_event_in_order() {
+        let log = Rc::new(RefCell::new(Log::default()));
+        let mut computer = Computer::new("3,9,1002,9,2,9,4,9,99,0", vec![]).unwrap();
+        computer.observe(Box::new(log.clone()));
+
+        assert_eq!(computer.run(), Ok(Status::AwaitingInput));
+        computer.push_input(21);
+        assert_eq!(computer.run(), Ok(Status::Halted));
+
+        assert_eq!(log.borrow().events, vec![
+            "before in [9]",
+            "before in [9]",
+            "input 21",
+            "write [9] = 21",
+            "after 0 -> 2",
+            "before mul [9], 2, [9]",
+            "read [9] = 21",
+            "write [9] = 42",
+            "after 2 -> 6",
+            "before out [9]",
+            "read [9] = 42",
+            "output 42",
+            "after 6 -> 8",
+            "before hlt",
+            "halt 8"
+        ]);
+    }
+
+    #[test]
+    fn breakpoints_stop_before_the_instruction() {
+        let log = Rc::new(RefCell::new(Log::default()));
+        let mut computer = Computer::new("104,1,104,2,99", vec![]).unwrap();
+        computer.observe(Box::new(Breakpoints::new(&[2])));
+        computer.observe(Box::new(log.clone()));
+
+        assert_eq!(computer.run(), Ok(Status::Breakpoint));
+        assert_eq!(computer.pointer(), 2);
+        assert_eq!(computer.outputs(), &[1]);
+
+        assert_eq!(computer.run(), Ok(Status::Halted));
+        assert_eq!(computer.outputs(), &[1, 2]);
+
+        // Every observer still sees the instruction that was stopped at, both
+        // times it is offered.
+        let befores = log.borrow().events.iter().filter(|event| event.starts_with("before")).count();
+        assert_eq!(befores, 4);
+    }
+}
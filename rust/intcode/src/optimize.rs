@@ -0,0 +1,514 @@
+//! A peephole optimizer that rewrites programs into equivalent ones.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::convert::TryFrom;
+
+use crate::decoder::{Opcode, OpcodeWithParamModes, ParamMode};
+
+/// A change the optimizer made, see `Optimized::rewrites`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rewrite {
+    /// A position mode param was replaced by the constant it always holds.
+    Propagated,
+    /// An add or multiply of constants was replaced by a store of the result.
+    Folded,
+    /// A jump that is always taken was made unconditional, with an
+    /// immediate target.
+    DirectJump,
+    /// A jump to an unconditional jump was pointed straight at where that one
+    /// goes.
+    ThreadedJump,
+    /// A jump that is never taken, or only goes to the next instruction, was
+    /// removed.
+    RemovedJump
+}
+
+/// The result of `optimize`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Optimized {
+    /// The optimized program.
+    pub memory: Vec<i32>,
+    /// Every change made, keyed by the address of the instruction in the
+    /// original program.
+    pub rewrites: Vec<(usize, Rewrite)>,
+    /// Whether instructions were removed, moving everything after them.
+    pub relocated: bool,
+    /// Why the program was left as it was, if it was.
+    pub refused: Option<String>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Param {
+    Position(usize),
+    Immediate(i32)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Insn {
+    opcode: Opcode,
+    // Params that are written to are always `Position`.
+    params: Vec<Param>
+}
+
+struct Analysis {
+    insns: BTreeMap<usize, Insn>,
+    reads: BTreeSet<usize>,
+    writes: BTreeSet<usize>,
+    leaders: BTreeSet<usize>
+}
+
+/// Rewrites the program in `memory`, starting at address 0, into one that
+/// gives the same outputs, final memory aside, for every input.
+///
+/// Within each straight-line run of code, cells known to hold a constant are
+/// propagated into the params that read them, adds and multiplies of
+/// constants are folded, jumps that are always taken are made direct and
+/// chains of jumps are threaded. Jumps that are never taken, or that only go
+/// to the next instruction, are then removed and everything after them
+/// moved down, with every address that refers to the moved words updated to
+/// match.
+///
+/// This all relies on knowing every instruction that can run, so the program
+/// is left unchanged if it writes to its own code, if it jumps to an address
+/// that is only known at runtime or if it runs into a word that is not an
+/// instruction. Instructions that the program reads as data are never
+/// rewritten, and nothing is moved if there are any.
+pub fn optimize(memory: &[i32]) -> Optimized {
+    let analysis = match analyse(memory) {
+        Ok(analysis) => analysis,
+        Err(reason) => return Optimized {
+            memory: memory.to_vec(),
+            rewrites: Vec::new(),
+            relocated: false,
+            refused: Some(reason)
+        }
+    };
+
+    let protected: BTreeSet<usize> = analysis.insns.iter()
+        .filter(|(&address, insn)| (address..address + insn.len()).any(|a| analysis.reads.contains(&a)))
+        .map(|(&address, _)| address)
+        .collect();
+
+    let mut insns = analysis.insns.clone();
+    let mut rewrites = Vec::new();
+    let mut removable = BTreeSet::new();
+
+    // Cells known to hold a constant on this straight-line run.
+    let mut known: HashMap<usize, i32> = HashMap::new();
+    let mut end = None;
+
+    for (&address, insn) in insns.iter_mut() {
+        if analysis.leaders.contains(&address) || end != Some(address) {
+            known.clear();
+        }
+        end = Some(address + insn.len());
+
+        let value = |param: Param, known: &HashMap<usize, i32>| match param {
+            Param::Immediate(value) => Some(value),
+            Param::Position(cell) if !analysis.writes.contains(&cell) => Some(memory[cell]),
+            Param::Position(cell) => known.get(&cell).copied()
+        };
+
+        let rewrite = !protected.contains(&address);
+        let reads = insn.reads();
+        let values: Vec<Option<i32>> = reads.iter().map(|&i| value(insn.params[i], &known)).collect();
+
+        match insn.opcode {
+            Opcode::JumpIfTrue | Opcode::JumpIfFalse => {
+                let taken = values[0].map(|c| (c != 0) == (insn.opcode == Opcode::JumpIfTrue));
+
+                match (taken, values[1]) {
+                    (Some(false), _) if rewrite => {
+                        removable.insert(address);
+                    },
+                    (Some(true), Some(target)) if rewrite && !insn.is_direct() => {
+                        *insn = Insn {
+                            opcode: Opcode::JumpIfTrue,
+                            params: vec![Param::Immediate(1), Param::Immediate(target)]
+                        };
+                        rewrites.push((address, Rewrite::DirectJump));
+                    },
+                    _ if rewrite && insn.propagate(&values) => rewrites.push((address, Rewrite::Propagated)),
+                    _ => {}
+                }
+            },
+            opcode => {
+                if rewrite && insn.propagate(&values) {
+                    rewrites.push((address, Rewrite::Propagated));
+                }
+
+                let result = match (opcode, values.first().copied().flatten(), values.get(1).copied().flatten()) {
+                    (Opcode::Add, Some(a), Some(b)) => a.checked_add(b),
+                    (Opcode::Multiply, Some(a), Some(b)) => a.checked_mul(b),
+                    (Opcode::LessThan, Some(a), Some(b)) => Some((a < b) as i32),
+                    (Opcode::Equals, Some(a), Some(b)) => Some((a == b) as i32),
+                    _ => None
+                };
+
+                if let (Opcode::Add, Some(result)) | (Opcode::Multiply, Some(result)) = (opcode, result) {
+                    let folded = vec![Param::Immediate(result), Param::Immediate(0), insn.params[2]];
+
+                    if rewrite && (opcode, &folded) != (Opcode::Add, &insn.params) {
+                        insn.opcode = Opcode::Add;
+                        insn.params = folded;
+                        rewrites.push((address, Rewrite::Folded));
+                    }
+                }
+
+                if let Some(Param::Position(to)) = insn.written() {
+                    match result {
+                        Some(result) => known.insert(to, result),
+                        None => known.remove(&to)
+                    };
+                }
+            }
+        }
+    }
+
+    // Thread unconditional jumps through any chain of them they lead to.
+    let mut threaded = Vec::new();
+    for (&address, insn) in insns.iter() {
+        if protected.contains(&address) || removable.contains(&address) {
+            continue;
+        }
+
+        let mut target = match insn.always_jumps_to() {
+            Some(target) => target,
+            None => continue
+        };
+
+        let mut seen = BTreeSet::new();
+        while let Some(next) = insns.get(&target).and_then(Insn::always_jumps_to) {
+            if !seen.insert(target) {
+                break;
+            }
+            target = next;
+        }
+
+        if Some(target) != insn.always_jumps_to() {
+            threaded.push((address, target));
+        }
+    }
+
+    for (address, target) in threaded {
+        insns.get_mut(&address).unwrap().params[1] = Param::Immediate(target as i32);
+        rewrites.push((address, Rewrite::ThreadedJump));
+    }
+
+    for (&address, insn) in insns.iter() {
+        if !protected.contains(&address) && insn.always_jumps_to() == Some(address + insn.len()) {
+            removable.insert(address);
+        }
+    }
+
+    let relocate = protected.is_empty() && !removable.is_empty();
+    let changed: BTreeSet<usize> = rewrites.iter().map(|&(address, _)| address).collect();
+
+    let memory = if relocate {
+        rewrites.extend(removable.iter().map(|&address| (address, Rewrite::RemovedJump)));
+        relocated(memory, &insns, &removable)
+    } else {
+        let mut memory = memory.to_vec();
+        for &address in &changed {
+            let words = insns[&address].encode();
+            memory[address..address + words.len()].copy_from_slice(&words);
+        }
+        memory
+    };
+
+    rewrites.sort_by_key(|&(address, _)| address);
+
+    Optimized { memory, rewrites, relocated: relocate, refused: None }
+}
+
+// Finds every instruction that can run, and every address that is read or
+// written. Cells that are never written hold their initial value throughout,
+// so the set of writes is grown until the jumps that read them agree with it.
+fn analyse(memory: &[i32]) -> Result<Analysis, String> {
+    let mut writes = BTreeSet::new();
+
+    loop {
+        let mut insns = BTreeMap::new();
+        let mut reads = BTreeSet::new();
+        let mut found_writes = BTreeSet::new();
+        let mut leaders = BTreeSet::new();
+        let mut work = vec![0];
+
+        leaders.insert(0);
+
+        while let Some(address) = work.pop() {
+            if insns.contains_key(&address) {
+                continue;
+            }
+
+            let insn = Insn::decode(memory, address)?;
+            let writable = insn.opcode.writable_params();
+
+            for (i, param) in insn.params.iter().enumerate() {
+                if let Param::Position(cell) = *param {
+                    if cell >= memory.len() {
+                        return Err(format!("The instruction at {} accesses {}, outside of memory.", address, cell));
+                    }
+
+                    if writable.contains(&(i + 1)) {
+                        found_writes.insert(cell);
+                    } else {
+                        reads.insert(cell);
+                    }
+                }
+            }
+
+            let value = |param: Param| match param {
+                Param::Immediate(value) => Some(value),
+                Param::Position(cell) if !writes.contains(&cell) => Some(memory[cell]),
+                Param::Position(_) => None
+            };
+
+            let next = address + insn.len();
+            match insn.opcode {
+                Opcode::Halt => {},
+                Opcode::JumpIfTrue | Opcode::JumpIfFalse => {
+                    let taken = value(insn.params[0]).map(|c| (c != 0) == (insn.opcode == Opcode::JumpIfTrue));
+
+                    if taken != Some(false) {
+                        let target = value(insn.params[1])
+                            .ok_or_else(|| format!("The jump at {} has a target only known at runtime.", address))?;
+                        let target = usize::try_from(target).ok()
+                            .filter(|&target| target < memory.len())
+                            .ok_or_else(|| format!("The jump at {} goes to {}, outside of memory.", address, target))?;
+
+                        leaders.insert(target);
+                        work.push(target);
+                    }
+
+                    leaders.insert(next);
+                    if taken != Some(true) {
+                        work.push(next);
+                    }
+                },
+                _ => work.push(next)
+            }
+
+            insns.insert(address, insn);
+        }
+
+        if found_writes != writes {
+            writes = found_writes;
+            continue;
+        }
+
+        let mut end = 0;
+        for (&address, insn) in &insns {
+            if address < end {
+                return Err(format!("The instructions at {} and before it overlap.", address));
+            }
+            end = address + insn.len();
+
+            if let Some(cell) = (address..end).find(|cell| writes.contains(cell)) {
+                return Err(format!("The instruction at {} is written to at {}.", address, cell));
+            }
+        }
+
+        return Ok(Analysis { insns, reads, writes, leaders });
+    }
+}
+
+// Lays the program out again without the `removed` instructions. Every
+// address maps to the first word at or after it that is kept, so jumps to a
+// removed instruction go to whatever it would have fallen through to.
+fn relocated(memory: &[i32], insns: &BTreeMap<usize, Insn>, removed: &BTreeSet<usize>) -> Vec<i32> {
+    let mut kept = vec![true; memory.len()];
+    for &address in removed {
+        for word in kept.iter_mut().skip(address).take(insns[&address].len()) {
+            *word = false;
+        }
+    }
+
+    let mut moved_to = vec![0; memory.len() + 1];
+    for (address, &kept) in kept.iter().enumerate() {
+        moved_to[address + 1] = moved_to[address] + kept as usize;
+    }
+
+    let mut relocated = Vec::new();
+    let mut address = 0;
+
+    while address < memory.len() {
+        match insns.get(&address) {
+            Some(insn) => {
+                if kept[address] {
+                    let jump = insn.opcode == Opcode::JumpIfTrue || insn.opcode == Opcode::JumpIfFalse;
+                    let params = insn.params.iter().enumerate().map(|(i, &param)| match param {
+                        Param::Position(cell) => Param::Position(moved_to[cell]),
+                        Param::Immediate(target) if jump && i == 1 => {
+                            // Every target that is kept was checked to be in memory by `analyse`.
+                            Param::Immediate(moved_to[target as usize] as i32)
+                        },
+                        param => param
+                    }).collect();
+
+                    relocated.extend(Insn { opcode: insn.opcode, params }.encode());
+                }
+                address += insn.len();
+            },
+            None => {
+                relocated.push(memory[address]);
+                address += 1;
+            }
+        }
+    }
+
+    relocated
+}
+
+impl Insn {
+    fn decode(memory: &[i32], address: usize) -> Result<Self, String> {
+        let decoded = OpcodeWithParamModes::try_from(memory[address])
+            .map_err(|reason| format!("The word at {} is not an instruction: {}", address, reason))?;
+        let writable = decoded.opcode.writable_params();
+        let len = decoded.opcode.num_params() + 1;
+
+        let words = memory.get(address + 1..address + len)
+            .ok_or_else(|| format!("The instruction at {} runs past the end of memory.", address))?;
+
+        let params = words.iter().enumerate().map(|(i, &word)| {
+            match (decoded.param_modes[i], writable.contains(&(i + 1))) {
+                (ParamMode::ImmediateMode, false) => Ok(Param::Immediate(word)),
+                _ => usize::try_from(word)
+                    .map(Param::Position)
+                    .map_err(|_| format!("The instruction at {} accesses {}, outside of memory.", address, word))
+            }
+        }).collect::<Result<_, _>>()?;
+
+        Ok(Insn { opcode: decoded.opcode, params })
+    }
+
+    fn encode(&self) -> Vec<i32> {
+        let writable = self.opcode.writable_params();
+        let mut word = self.opcode as i32;
+        let mut words = vec![0];
+
+        for (i, param) in self.params.iter().enumerate() {
+            match *param {
+                Param::Immediate(value) => {
+                    if !writable.contains(&(i + 1)) {
+                        word += 10i32.pow(i as u32 + 2);
+                    }
+                    words.push(value);
+                },
+                Param::Position(cell) => words.push(cell as i32)
+            }
+        }
+
+        words[0] = word;
+        words
+    }
+
+    fn len(&self) -> usize {
+        self.params.len() + 1
+    }
+
+    // The indices of the params that are read.
+    fn reads(&self) -> Vec<usize> {
+        let writable = self.opcode.writable_params();
+        (0..self.params.len()).filter(|i| !writable.contains(&(i + 1))).collect()
+    }
+
+    fn written(&self) -> Option<Param> {
+        let writable = self.opcode.writable_params();
+        (0..self.params.len()).find(|i| writable.contains(&(i + 1))).map(|i| self.params[i])
+    }
+
+    // Replaces params that are read with the constants in `values`, one for
+    // each of `reads`, returning whether any changed.
+    fn propagate(&mut self, values: &[Option<i32>]) -> bool {
+        let mut changed = false;
+
+        for (&i, value) in self.reads().iter().zip(values) {
+            if let (Param::Position(_), Some(value)) = (self.params[i], value) {
+                self.params[i] = Param::Immediate(*value);
+                changed = true;
+            }
+        }
+
+        changed
+    }
+
+    fn is_direct(&self) -> bool {
+        self.params.iter().all(|param| matches!(param, Param::Immediate(_)))
+    }
+
+    fn always_jumps_to(&self) -> Option<usize> {
+        match (self.opcode, &self.params[..]) {
+            (Opcode::JumpIfTrue, &[Param::Immediate(c), Param::Immediate(target)]) if c != 0 => usize::try_from(target).ok(),
+            (Opcode::JumpIfFalse, &[Param::Immediate(0), Param::Immediate(target)]) => usize::try_from(target).ok(),
+            _ => None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{optimize, Rewrite};
+    use crate::computer::Computer;
+
+    fn outputs(memory: &[i32], input: Vec<i32>) -> Vec<i32> {
+        let mut computer = Computer::with_memory(memory.to_vec(), input);
+        computer.exec();
+        computer.outputs().to_vec()
+    }
+
+    #[test]
+    fn folds_and_propagates_constants() {
+        // Adds 2 and 3, multiplies that by 4 and outputs it.
+        let program = [1101, 2, 3, 11, 1002, 11, 4, 11, 4, 11, 99, 0];
+        let optimized = optimize(&program);
+
+        assert_eq!(optimized.memory, vec![1101, 5, 0, 11, 1101, 20, 0, 11, 104, 20, 99, 0]);
+        assert_eq!(optimized.rewrites, vec![
+            (0, Rewrite::Folded),
+            (4, Rewrite::Propagated),
+            (4, Rewrite::Folded),
+            (8, Rewrite::Propagated)
+        ]);
+        assert!(!optimized.relocated);
+        assert_eq!(outputs(&optimized.memory, vec![]), outputs(&program, vec![]));
+    }
+
+    #[test]
+    fn removes_and_threads_jumps() {
+        let program = [
+            1105, 0, 0,     // never jumps
+            1005, 15, 9,    // always jumps, as 15 is never written
+            104, 7, 99,     // never runs
+            1106, 0, 12,    // always jumps, to the next instruction
+            4, 15,
+            99,
+            1
+        ];
+        let optimized = optimize(&program);
+
+        assert_eq!(optimized.memory, vec![1105, 1, 6, 104, 7, 99, 104, 1, 99, 1]);
+        assert_eq!(optimized.rewrites, vec![
+            (0, Rewrite::RemovedJump),
+            (3, Rewrite::DirectJump),
+            (3, Rewrite::ThreadedJump),
+            (9, Rewrite::RemovedJump),
+            (12, Rewrite::Propagated)
+        ]);
+        assert!(optimized.relocated);
+        assert_eq!(outputs(&optimized.memory, vec![]), vec![1]);
+    }
+
+    #[test]
+    fn refuses_self_modifying_code() {
+        let program = [1002, 4, 3, 4, 33];
+        let optimized = optimize(&program);
+
+        assert_eq!(optimized.memory, program.to_vec());
+        assert!(optimized.rewrites.is_empty());
+        assert_eq!(optimized.refused, Some("The word at 4 is not an instruction: Unknown opcode '33' encountered.".to_string()));
+
+        let program = [3, 3, 1105, -1, 7, 104, 0, 99];
+        assert_eq!(optimize(&program).refused, Some("The instruction at 2 is written to at 3.".to_string()));
+    }
+}
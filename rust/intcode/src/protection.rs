@@ -0,0 +1,155 @@
+//! Restricting how a program may use parts of memory.
+
+use std::fmt;
+use std::ops::Range;
+
+use crate::error::Error;
+
+/// What a program may do with a region of memory, see `Computer::protect`.
+///
+/// Where regions overlap, the one declared last wins, so a program image can
+/// be made `ReadOnly` with a `NoExecute` data segment declared inside it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Protection {
+    /// Anything goes, as for memory with no region at all.
+    ReadWrite,
+    /// Can be read and executed, but not written to.
+    ReadOnly,
+    /// Can be read and written to, but not executed.
+    NoExecute,
+    /// Cannot be used at all.
+    NoAccess
+}
+
+/// A use of memory that a `Protection` might forbid.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    /// Fetching an instruction word, or one of its raw params.
+    Execute,
+    /// Reading a param in position mode.
+    Read,
+    /// Writing the result of an instruction.
+    Write
+}
+
+impl Protection {
+    pub fn allows(self, access: Access) -> bool {
+        match (self, access) {
+            (Protection::ReadWrite, _) => true,
+            (Protection::ReadOnly, Access::Write) => false,
+            (Protection::ReadOnly, _) => true,
+            (Protection::NoExecute, Access::Execute) => false,
+            (Protection::NoExecute, _) => true,
+            (Protection::NoAccess, _) => false
+        }
+    }
+}
+
+impl fmt::Display for Protection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Protection::ReadWrite => write!(f, "read-write"),
+            Protection::ReadOnly => write!(f, "read-only"),
+            Protection::NoExecute => write!(f, "no-execute"),
+            Protection::NoAccess => write!(f, "no-access")
+        }
+    }
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Access::Execute => write!(f, "execute"),
+            Access::Read => write!(f, "read"),
+            Access::Write => write!(f, "write")
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Region {
+    pub range: Range<usize>,
+    pub protection: Protection
+}
+
+// Checks `access` to `address` against the last of `regions` that covers it,
+// blaming the instruction at `pointer` if it is not allowed.
+pub(crate) fn check(regions: &[Region], pointer: usize, address: usize, access: Access) -> Result<(), Error> {
+    match regions.iter().rev().find(|region| region.range.contains(&address)) {
+        Some(region) if !region.protection.allows(access) => Err(Error::Protected {
+            pointer,
+            address,
+            access,
+            protection: region.protection,
+            region: region.range.clone()
+        }),
+        _ => Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Access, Protection};
+    use crate::computer::{Computer, Status};
+    use crate::error::Error;
+
+    // Doubles its input into 11 and outputs it.
+    const DOUBLE: &str = "3,11,1002,11,2,11,4,11,99,0,0,0";
+
+    #[test]
+    fn allows_a_declared_data_segment() {
+        let mut computer = Computer::new(DOUBLE, vec![21]).unwrap();
+        computer.protect(0..12, Protection::ReadOnly).unwrap();
+        computer.protect(9..12, Protection::NoExecute).unwrap();
+
+        assert_eq!(computer.run(), Ok(Status::Halted));
+        assert_eq!(computer.outputs(), &[42]);
+    }
+
+    #[test]
+    fn stops_on_the_first_violation() {
+        let mut computer = Computer::new(DOUBLE, vec![21]).unwrap();
+        computer.protect(0..12, Protection::ReadOnly).unwrap();
+
+        let err = computer.run().unwrap_err();
+        assert_eq!(err, Error::Protected {
+            pointer: 0,
+            address: 11,
+            access: Access::Write,
+            protection: Protection::ReadOnly,
+            region: 0..12
+        });
+        assert_eq!(err.to_string(), "Instruction at 0 tried to write address 11, which is in read-only region 0..12.");
+
+        let mut computer = Computer::new(DOUBLE, vec![21]).unwrap();
+        computer.protect(10..12, Protection::NoAccess).unwrap();
+        assert_eq!(computer.run().unwrap_err().to_string(), "Instruction at 0 tried to write address 11, which is in no-access region 10..12.");
+
+        // Jumps into a no-execute region, at its second word.
+        let mut computer = Computer::new("1105,1,4,99,104,0,99", vec![]).unwrap();
+        computer.protect(3..7, Protection::NoExecute).unwrap();
+        assert_eq!(computer.run().unwrap_err(), Error::Protected {
+            pointer: 4,
+            address: 4,
+            access: Access::Execute,
+            protection: Protection::NoExecute,
+            region: 3..7
+        });
+    }
+
+    #[test]
+    fn protects_params_as_well_as_instruction_words() {
+        let mut computer = Computer::new("104,7,99", vec![]).unwrap();
+        computer.protect(1..2, Protection::NoExecute).unwrap();
+        assert_eq!(computer.run().unwrap_err().to_string(), "Instruction at 0 tried to execute address 1, which is in no-execute region 1..2.");
+
+        let mut computer = Computer::new("4,3,99,7", vec![]).unwrap();
+        computer.protect(3..4, Protection::NoAccess).unwrap();
+        assert_eq!(computer.run().unwrap_err().to_string(), "Instruction at 0 tried to read address 3, which is in no-access region 3..4.");
+
+        assert_eq!(
+            Computer::new("99", vec![]).unwrap().protect(2..2, Protection::ReadOnly),
+            Err(Error::InvalidMapping { range: 2..2, reason: "The range is empty.".to_string() })
+        );
+    }
+}
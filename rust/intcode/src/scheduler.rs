@@ -0,0 +1,257 @@
+//! Running many computers together, passing messages between them.
+
+use std::convert::TryFrom;
+
+use crate::computer::{Computer, Status};
+use crate::error::Error;
+
+/// A message sent to the machine at address `to`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Packet {
+    pub to: i32,
+    pub values: Vec<i32>
+}
+
+/// How long each machine runs for in a round.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Slice {
+    Instructions(usize),
+    /// At least this many cycles, as counted by each machine's `CostTable`,
+    /// see `Computer::run_for_cycles`.
+    Cycles(u64)
+}
+
+/// How a call to `Scheduler::run` finished.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    /// Every machine was parked or halted, with nothing sent, for the
+    /// number of rounds given to `idle_after`. `round` is the last of them.
+    Idle { round: usize },
+    /// Every machine has halted.
+    Halted { round: usize },
+    /// The limit on rounds was reached first.
+    OutOfRounds
+}
+
+/// Runs a set of computers round-robin, routing what they output to each
+/// other as packets.
+///
+/// Each machine's address is the order it was added in, starting at 0. A
+/// machine sends a packet by outputting the address it is for followed by
+/// the values in it, and the values are queued as input for that machine.
+/// Packets for an address without a machine are kept in `unrouted`.
+///
+/// Each round runs every machine in address order for a slice of
+/// instructions, or of cycles. Machines waiting for input are parked, and
+/// not run, until a packet arrives for them. Packets sent during a round are
+/// delivered at the end of it, in the order they were sent. There are no
+/// threads or clocks involved, so the same machines and packets always play
+/// out the same way.
+#[derive(Debug)]
+pub struct Scheduler {
+    machines: Vec<Computer>,
+    // How many outputs of each machine have been turned into packets.
+    sent: Vec<usize>,
+    slice: Slice,
+    packet_len: usize,
+    idle_after: usize,
+    starved: usize,
+    round: usize,
+    unrouted: Vec<Packet>
+}
+
+impl Scheduler {
+    /// Creates a scheduler that runs each machine for `slice` instructions a
+    /// round, and that sends packets of `packet_len` values.
+    pub fn new(slice: usize, packet_len: usize) -> Self {
+        Scheduler {
+            machines: Vec::new(),
+            sent: Vec::new(),
+            slice: Slice::Instructions(slice),
+            packet_len,
+            idle_after: 1,
+            starved: 0,
+            round: 0,
+            unrouted: Vec::new()
+        }
+    }
+
+    /// Runs each machine for a slice of at least `cycles` cycles a round,
+    /// instead of a number of instructions.
+    pub fn cycle_slices(mut self, cycles: u64) -> Self {
+        self.slice = Slice::Cycles(cycles);
+        self
+    }
+
+    /// Sets how many rounds in a row every machine must be starved for
+    /// before the network counts as idle. Defaults to 1.
+    pub fn idle_after(mut self, rounds: usize) -> Self {
+        self.idle_after = rounds.max(1);
+        self
+    }
+
+    /// Adds a machine, returning its address.
+    pub fn add(&mut self, computer: Computer) -> usize {
+        self.machines.push(computer);
+        self.sent.push(0);
+        self.machines.len() - 1
+    }
+
+    pub fn machine(&self, address: usize) -> Option<&Computer> {
+        self.machines.get(address)
+    }
+
+    /// The number of rounds run so far.
+    pub fn rounds(&self) -> usize {
+        self.round
+    }
+
+    /// Every packet sent to an address without a machine, oldest first.
+    pub fn unrouted(&self) -> &[Packet] {
+        &self.unrouted
+    }
+
+    /// Delivers `packet` straight away, waking the machine it is for.
+    pub fn send(&mut self, packet: Packet) {
+        let machine = usize::try_from(packet.to).ok().and_then(|to| self.machines.get_mut(to));
+
+        match machine {
+            Some(machine) => {
+                for &value in &packet.values {
+                    machine.push_input(value);
+                }
+            },
+            None => self.unrouted.push(packet)
+        }
+    }
+
+    /// Runs a single round, returning whether every machine was starved for
+    /// all of it. Fails with the address of the first machine to fail.
+    pub fn round(&mut self) -> Result<bool, (usize, Error)> {
+        let mut starved = true;
+        let mut packets = Vec::new();
+
+        for (address, machine) in self.machines.iter_mut().enumerate() {
+            if machine.status() == Status::AwaitingInput || machine.status() == Status::Halted {
+                continue;
+            }
+
+            starved = false;
+            let ran = match self.slice {
+                Slice::Instructions(instructions) => machine.run_for(instructions),
+                Slice::Cycles(cycles) => machine.run_for_cycles(cycles)
+            };
+            ran.map_err(|err| (address, err))?;
+
+            // Only whole packets are sent, the rest wait for the next round.
+            let pending = &machine.outputs()[self.sent[address]..];
+            for packet in pending.chunks_exact(self.packet_len + 1) {
+                packets.push(Packet { to: packet[0], values: packet[1..].to_vec() });
+                self.sent[address] += packet.len();
+            }
+        }
+
+        starved &= packets.is_empty();
+        for packet in packets {
+            self.send(packet);
+        }
+
+        self.round += 1;
+        Ok(starved)
+    }
+
+    /// Runs rounds until the network is idle, every machine has halted, or
+    /// `max_rounds` more have been run.
+    pub fn run(&mut self, max_rounds: usize) -> Result<Outcome, (usize, Error)> {
+        for _ in 0..max_rounds {
+            if self.machines.iter().all(|machine| machine.status() == Status::Halted) {
+                return Ok(Outcome::Halted { round: self.round });
+            }
+
+            if self.round()? {
+                self.starved += 1;
+            } else {
+                self.starved = 0;
+            }
+
+            if self.starved >= self.idle_after {
+                return Ok(Outcome::Idle { round: self.round });
+            }
+        }
+
+        Ok(Outcome::OutOfRounds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Outcome, Packet, Scheduler};
+    use crate::computer::{Computer, Status};
+    use crate::error::Error;
+
+    // Adds 1 to each value it is sent, passing it on to the machine whose
+    // address is at 14 until it reaches 10, when it goes to 99 instead.
+    fn ring(machines: usize, slice: usize, idle_after: usize) -> Scheduler {
+        let mut program = vec![
+            3, 50, 1001, 50, 1, 50, 1007, 50, 10, 51, 1006, 51, 20,
+            104, 0, 4, 50, 1105, 1, 0,
+            104, 99, 4, 50, 1105, 1, 0
+        ];
+        program.resize(52, 0);
+
+        let mut scheduler = Scheduler::new(slice, 1).idle_after(idle_after);
+        for address in 0..machines {
+            let computer = Computer::with_memory(program.clone(), vec![])
+                .with_patches(&[(14, ((address + 1) % machines) as i32)])
+                .unwrap();
+            scheduler.add(computer);
+        }
+
+        scheduler
+    }
+
+    #[test]
+    fn passes_packets_until_idle() {
+        let mut scheduler = ring(3, 5, 3);
+        scheduler.send(Packet { to: 0, values: vec![0] });
+
+        let outcome = scheduler.run(1000);
+        assert_eq!(outcome, Ok(Outcome::Idle { round: scheduler.rounds() }));
+        assert_eq!(scheduler.unrouted(), &[Packet { to: 99, values: vec![10] }]);
+        assert_eq!(scheduler.machine(0).unwrap().status(), Status::AwaitingInput);
+
+        // The same network always plays out the same way.
+        let mut again = ring(3, 5, 3);
+        again.send(Packet { to: 0, values: vec![0] });
+        assert_eq!(again.run(1000), outcome);
+        assert_eq!(again.rounds(), scheduler.rounds());
+
+        // A smaller slice needs more rounds to get there.
+        let mut slower = ring(3, 1, 3);
+        slower.send(Packet { to: 0, values: vec![0] });
+        assert!(slower.run(1000).is_ok());
+        assert!(slower.rounds() > scheduler.rounds());
+
+        // Slices of cycles play out the same way each time too.
+        let mut cycles = ring(3, 5, 3).cycle_slices(4);
+        cycles.send(Packet { to: 0, values: vec![0] });
+        assert!(cycles.run(1000).is_ok());
+        assert_eq!(cycles.unrouted(), scheduler.unrouted());
+        assert!(cycles.rounds() > scheduler.rounds());
+    }
+
+    #[test]
+    fn reports_halting_and_failing_machines() {
+        let mut scheduler = Scheduler::new(10, 1);
+        scheduler.add(Computer::new("104,1,104,7,104,1,104,8,99", vec![]).unwrap());
+        scheduler.add(Computer::new("3,9,3,10,4,9,4,10,99,0,0", vec![]).unwrap());
+
+        assert_eq!(scheduler.run(10), Ok(Outcome::Halted { round: 2 }));
+        assert_eq!(scheduler.unrouted(), &[Packet { to: 7, values: vec![8] }]);
+
+        let mut scheduler = Scheduler::new(10, 1);
+        scheduler.add(Computer::new("99", vec![]).unwrap());
+        scheduler.add(Computer::new("4,20,99", vec![]).unwrap());
+        assert_eq!(scheduler.run(10), Err((1, Error::InvalidAddress { pointer: 0, address: 20 })));
+    }
+}
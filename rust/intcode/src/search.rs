@@ -0,0 +1,362 @@
+//! Searching for inputs that drive a program somewhere interesting, by
+//! mutating inputs that have already got somewhere new.
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+
+use crate::computer::{Computer, Status};
+use crate::coverage::Coverage;
+use crate::decoder::{OpcodeWithParamModes, ParamMode};
+use crate::error::Error;
+use crate::image::Image;
+use crate::minimize;
+
+/// What one run of a `Search` did, as shown to its scoring function.
+#[derive(Debug)]
+pub struct Run<'a> {
+    pub input: &'a [i32],
+    pub outputs: &'a [i32],
+    /// `Status::Running` if the run used up its budget.
+    pub result: &'a Result<Status, Error>,
+    pub coverage: &'a Coverage
+}
+
+/// What a `Search` found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Found {
+    /// Every input that covered something new or scored higher than any
+    /// before it, in the order they were found.
+    pub corpus: Vec<Vec<i32>>,
+    /// Everything covered by every run.
+    pub coverage: Coverage,
+    /// The highest scoring input and its score.
+    pub best: Option<(Vec<i32>, i64)>,
+    /// The first input found to fail at each address, by address.
+    pub crashes: BTreeMap<usize, (Vec<i32>, Error)>,
+    /// The first input found to make the interpreter panic at each address,
+    /// with the panic message, by address.
+    pub panics: BTreeMap<usize, (Vec<i32>, String)>,
+    /// How many runs used up their budget without covering anything new.
+    pub timeouts: usize,
+    pub runs: usize
+}
+
+/// Looks for inputs to a program by mutating the inputs that have already
+/// found new coverage, or a better score, so far.
+///
+/// Each run shares the same parsed image and is given at most `budget`
+/// instructions. An input is kept, and mutated again later, if its run
+/// executed an instruction or took a branch no run had before, or if the
+/// scoring function rated it higher than any before it. Runs that fail with
+/// an `Error` are collected as crashes rather than kept, and runs that make
+/// the interpreter itself panic are caught and collected as panics.
+///
+/// Inputs are mutated by changing, inserting, removing or appending values.
+/// New values are mostly taken from the constants the program's instructions
+/// use, so that comparisons against them can be passed, or are small. The
+/// search is deterministic for a given `seed`.
+///
+/// ```
+/// use intcode::search::Search;
+///
+/// // Fails writing out of range, but only when the input is 7.
+/// let program = vec![3, 14, 1008, 14, 7, 14, 1005, 14, 10, 99, 1101, 0, 0, 100, 0];
+/// let found = Search::new(program).explore();
+///
+/// assert_eq!(found.crashes[&10].0, vec![7]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Search {
+    image: Image,
+    seeds: Vec<Vec<i32>>,
+    budget: usize,
+    runs: usize,
+    max_len: usize,
+    seed: u64
+}
+
+impl Search {
+    /// Creates a search over `memory`, starting from an empty input.
+    pub fn new(memory: Vec<i32>) -> Self {
+        Search {
+            image: Image::new(memory),
+            seeds: Vec::new(),
+            budget: 10_000,
+            runs: 1_000,
+            max_len: 32,
+            seed: 0x2019_1205
+        }
+    }
+
+    /// Starts the search from `input` as well.
+    pub fn input(mut self, input: Vec<i32>) -> Self {
+        self.seeds.push(input);
+        self
+    }
+
+    /// Sets how many instructions each run may execute, 10,000 by default.
+    pub fn budget(mut self, instructions: usize) -> Self {
+        self.budget = instructions;
+        self
+    }
+
+    /// Sets how many runs to make in total, 1,000 by default.
+    pub fn runs(mut self, runs: usize) -> Self {
+        self.runs = runs;
+        self
+    }
+
+    /// Sets how many values an input may grow to, 32 by default.
+    pub fn max_len(mut self, max_len: usize) -> Self {
+        self.max_len = max_len;
+        self
+    }
+
+    /// Seeds the random choices the search makes.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Searches for new coverage alone.
+    pub fn explore(&self) -> Found {
+        self.maximize(|_| 0)
+    }
+
+    /// Searches for new coverage and for inputs that `score` rates highly,
+    /// eg. by the largest output or by whether an address was reached.
+    pub fn maximize<S>(&self, mut score: S) -> Found
+        where S: FnMut(&Run) -> i64 {
+
+        let mut rng = XorShift(self.seed.max(1));
+        let dictionary = dictionary(self.image.words());
+
+        let mut found = Found {
+            corpus: Vec::new(),
+            coverage: Coverage::default(),
+            best: None,
+            crashes: BTreeMap::new(),
+            panics: BTreeMap::new(),
+            timeouts: 0,
+            runs: 0
+        };
+
+        let mut queue = if self.seeds.is_empty() { vec![Vec::new()] } else { self.seeds.clone() };
+
+        while found.runs < self.runs {
+            let input = if queue.is_empty() {
+                let parent = &found.corpus[rng.below(found.corpus.len())];
+                self.mutate(parent, &dictionary, &mut rng)
+            } else {
+                queue.remove(0)
+            };
+
+            self.try_input(input, &mut score, &mut found);
+
+            // Without anything to mutate, start again from nothing.
+            if found.corpus.is_empty() && queue.is_empty() {
+                queue.push(self.mutate(&[], &dictionary, &mut rng));
+            }
+        }
+
+        found
+    }
+
+    fn try_input<S>(&self, input: Vec<i32>, score: &mut S, found: &mut Found)
+        where S: FnMut(&Run) -> i64 {
+
+        let coverage = Rc::new(RefCell::new(Coverage::default()));
+        let mut computer = Computer::with_image(self.image.clone(), input.clone());
+        computer.observe(Box::new(coverage.clone()));
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| computer.run_for(self.budget)));
+        let coverage = coverage.borrow().clone();
+        found.runs += 1;
+
+        let result = match result {
+            Ok(result) => result,
+            Err(payload) => {
+                found.coverage.merge(&coverage);
+                found.panics.entry(computer.pointer()).or_insert((input, panic_message(payload)));
+                return;
+            }
+        };
+
+        let value = score(&Run { input: &input, outputs: computer.outputs(), result: &result, coverage: &coverage });
+        let new = covers_more(&found.coverage, &coverage);
+        found.coverage.merge(&coverage);
+
+        match result {
+            Err(err) => {
+                found.crashes.entry(computer.pointer()).or_insert((input, err));
+                return;
+            },
+            Ok(Status::Running) if !new => found.timeouts += 1,
+            _ => {}
+        }
+
+        let better = found.best.as_ref().is_none_or(|(_, best)| value > *best);
+        if better {
+            found.best = Some((input.clone(), value));
+        }
+
+        if new || better {
+            found.corpus.push(input);
+        }
+    }
+
+    fn mutate(&self, parent: &[i32], dictionary: &[i32], rng: &mut XorShift) -> Vec<i32> {
+        let mut input = parent.to_vec();
+
+        // Stack a few mutations, so that more than one value can change.
+        for _ in 0..=rng.below(2) {
+            let value = match rng.below(4) {
+                0 | 1 => dictionary[rng.below(dictionary.len())],
+                2 => rng.below(201) as i32 - 100,
+                _ => rng.next() as i32
+            };
+
+            match rng.below(5) {
+                0 | 1 if !input.is_empty() => {
+                    let i = rng.below(input.len());
+                    input[i] = value;
+                },
+                2 if !input.is_empty() => {
+                    let i = rng.below(input.len());
+                    input[i] = input[i].wrapping_add(rng.below(17) as i32 - 8);
+                },
+                3 if !input.is_empty() => {
+                    input.remove(rng.below(input.len()));
+                },
+                4 if input.len() < self.max_len => {
+                    let i = rng.below(input.len() + 1);
+                    input.insert(i, value);
+                },
+                _ if input.len() < self.max_len => input.push(value),
+                _ => {}
+            }
+        }
+
+        input
+    }
+}
+
+// The constants the program works with, and either side of them, taken
+// from the params its instructions read in immediate mode.
+fn dictionary(memory: &[i32]) -> Vec<i32> {
+    let mut words: BTreeSet<i32> = vec![0, 1, -1].into_iter().collect();
+
+    for (address, len) in minimize::instructions(memory) {
+        let decoded = OpcodeWithParamModes::try_from(memory[address]).unwrap();
+        let writable = decoded.opcode.writable_params();
+
+        for i in (0..len - 1).filter(|i| !writable.contains(&(i + 1))) {
+            if decoded.param_modes[i] == ParamMode::ImmediateMode {
+                let word = memory[address + i + 1];
+                words.extend(&[word, word.wrapping_sub(1), word.wrapping_add(1)]);
+            }
+        }
+    }
+
+    words.into_iter().collect()
+}
+
+// Whether `run` executed an instruction, or took a branch a way, that
+// `seen` does not have.
+fn covers_more(seen: &Coverage, run: &Coverage) -> bool {
+    !run.executed.is_subset(&seen.executed) || run.branches.iter().any(|(address, branch)| {
+        let before = seen.branches.get(address).copied().unwrap_or_default();
+        (branch.taken && !before.taken) || (branch.not_taken && !before.not_taken)
+    })
+}
+
+// A xorshift64 generator: not random enough for anything but this, but
+// deterministic everywhere and without any dependencies.
+#[derive(Debug, Clone)]
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => payload.downcast_ref::<&str>().map_or("The interpreter panicked.", |message| message).to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Search;
+    use crate::error::Error;
+
+    // Fails writing to 1000, but only if the inputs are 7 and then 3.
+    fn nested() -> Vec<i32> {
+        let mut program = vec![
+            3, 100, 3, 101,
+            1008, 100, 7, 102, 1006, 102, 30,
+            1008, 101, 3, 102, 1006, 102, 30,
+            1101, 0, 0, 1000
+        ];
+        program.resize(103, 0);
+        program[30] = 99;
+        program
+    }
+
+    #[test]
+    fn finds_inputs_that_reach_new_branches() {
+        let found = Search::new(nested()).explore();
+
+        assert_eq!(found.runs, 1_000);
+        assert!(found.coverage.executed.contains(&18));
+        assert_eq!(found.crashes.len(), 1);
+
+        let (input, err) = &found.crashes[&18];
+        assert_eq!(&input[..2], &[7, 3]);
+        assert_eq!(err, &Error::InvalidAddress { pointer: 18, address: 1000 });
+
+        // Each kept input covered something new, so there are few of them.
+        assert!(found.corpus.len() < 10);
+        assert_eq!(found, Search::new(nested()).explore());
+    }
+
+    #[test]
+    fn maximizes_a_score() {
+        // Outputs x * (100 - x).
+        let mut program = vec![3, 20, 1002, 20, -1, 21, 1001, 21, 100, 21, 2, 20, 21, 21, 4, 21, 99];
+        program.resize(22, 0);
+
+        let found = Search::new(program)
+            .runs(2_000)
+            .maximize(|run| run.outputs.first().copied().map_or(i64::MIN, i64::from));
+
+        let (input, score) = found.best.unwrap();
+        assert_eq!(score, 2_500);
+        assert_eq!(input[0], 50);
+    }
+
+    #[test]
+    fn enforces_a_budget() {
+        // Loops forever unless the input is 0.
+        let found = Search::new(vec![3, 7, 1005, 7, 2, 99, 0, 0]).budget(100).runs(50).explore();
+
+        assert_eq!(found.crashes.len(), 0);
+        assert!(found.timeouts > 0);
+        assert!(found.corpus.iter().any(|input| input.first() == Some(&0)));
+        assert!(found.coverage.executed.contains(&5));
+    }
+}
@@ -0,0 +1,361 @@
+//! Recording everything a run depends on, so that it can be replayed exactly.
+//!
+//! A session file holds `key = value` lines, with `#` starting a comment.
+//! Instructions are counted from 0 in the order they ran.
+//!
+//! ```text
+//! # Doubles its input, which arrived after it had waited for it.
+//! program = 3,9,1002,9,2,9,4,9,99,0
+//! waits = 0
+//! input[0] = 21
+//! output[2] = 42
+//! trace = 0,2,6,8
+//! ```
+//!
+//! * `program`: the memory when the run started, comma-separated.
+//! * `waits`: the instructions at which the program asked for input before
+//!   any had arrived, comma-separated.
+//! * `input[N]`: a value read by instruction `N`.
+//! * `output[N]`: a value output by instruction `N`.
+//! * `trace`: the address of each instruction that ran, comma-separated.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::computer::{Computer, Status};
+use crate::decoder::{Opcode, OpcodeWithParamModes};
+use crate::format::{self, Format};
+use crate::observer::{Control, Observer};
+
+/// Everything a run consumed, and what it did with it. See the module docs
+/// for the file format.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Session {
+    pub program: Vec<i32>,
+    pub waits: Vec<usize>,
+    pub inputs: Vec<(usize, i32)>,
+    pub outputs: Vec<(usize, i32)>,
+    pub trace: Vec<usize>
+}
+
+/// Where a replay first stopped doing what was recorded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    /// The number of the first instruction that behaved differently.
+    pub instruction: usize,
+    /// The address that instruction ran at when it was recorded.
+    pub pointer: Option<usize>,
+    pub reason: String
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.pointer {
+            Some(pointer) => write!(f, "Diverged at instruction {} (at {}): {}", self.instruction, pointer, self.reason),
+            None => write!(f, "Diverged at instruction {}, after the recording ended: {}", self.instruction, self.reason)
+        }
+    }
+}
+
+/// Records a `Session` from a `Computer` it is attached to, from the first
+/// instruction onwards.
+///
+/// Values pushed to the computer while it runs, whether by hand or by a
+/// `Scheduler` delivering packets, are recorded along with the instruction
+/// that read them, so that networked machines can be replayed one at a time.
+#[derive(Debug, Default)]
+pub struct Recorder {
+    session: Session,
+    started: bool
+}
+
+impl Recorder {
+    /// The session recorded so far.
+    pub fn session(&self) -> Session {
+        self.session.clone()
+    }
+}
+
+impl Observer for Recorder {
+    fn before(&mut self, computer: &Computer, instruction: &OpcodeWithParamModes) -> Control {
+        if !self.started {
+            self.session.program = computer.memory().to_vec();
+            self.started = true;
+        }
+
+        let at = self.session.trace.len();
+        if instruction.opcode == Opcode::Input && computer.pending_input() == 0 && self.session.waits.last() != Some(&at) {
+            self.session.waits.push(at);
+        }
+
+        Control::Continue
+    }
+
+    fn after(&mut self, _computer: &Computer, pointer: usize, _instruction: &OpcodeWithParamModes) {
+        self.session.trace.push(pointer);
+    }
+
+    fn input(&mut self, value: i32) {
+        self.session.inputs.push((self.session.trace.len(), value));
+    }
+
+    fn output(&mut self, value: i32) {
+        self.session.outputs.push((self.session.trace.len(), value));
+    }
+
+    fn halt(&mut self, computer: &Computer) {
+        self.session.trace.push(computer.pointer());
+    }
+}
+
+impl Session {
+    /// Parses a session file.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut session = Session::default();
+
+        for (i, line) in text.lines().enumerate() {
+            let at = |reason: String| format!("line {}: {}", i + 1, reason);
+            let line = line.split('#').next().unwrap().trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            let (key, value) = line.split_once('=')
+                .map(|(key, value)| (key.trim(), value.trim()))
+                .ok_or_else(|| at(format!("Expected 'key = value' but got '{}'.", line)))?;
+
+            match key {
+                "program" => {
+                    session.program = format::parse_as(Format::CommaSeparated, value.as_bytes()).map_err(|err| at(err.to_string()))?;
+                },
+                "waits" => session.waits = numbers(value).map_err(at)?,
+                "trace" => session.trace = numbers(value).map_err(at)?,
+                _ => {
+                    let (events, index) = match key.split_once('[') {
+                        Some(("input", index)) => (&mut session.inputs, index),
+                        Some(("output", index)) => (&mut session.outputs, index),
+                        _ => return Err(at(format!("Unknown key '{}'.", key)))
+                    };
+                    let index = index.strip_suffix(']').and_then(|index| index.parse().ok())
+                        .ok_or_else(|| at(format!("Expected an instruction number in '{}'.", key)))?;
+                    let value = value.parse().map_err(|err| at(format!("Failed to parse '{}' as i32: {}", value, err)))?;
+
+                    events.push((index, value));
+                }
+            }
+        }
+
+        Ok(session)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|err| format!("Could not read {}: {}", path.display(), err))?;
+        Session::parse(&text).map_err(|err| format!("{}, {}", path.display(), err))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        fs::write(path, self.to_string()).map_err(|err| format!("Could not write {}: {}", path.display(), err))
+    }
+
+    /// Replays the session on `computer`, which should hold the recorded
+    /// program, checking that it does exactly what was recorded.
+    ///
+    /// Each input is only queued once the instruction that read it is next,
+    /// and only after the computer has waited for it if it was recorded
+    /// waiting. The replay stops where the recording did, and the computer is
+    /// returned as it was then. If anything differs, the first instruction
+    /// that did is returned instead.
+    pub fn replay(&self, mut computer: Computer) -> Result<Computer, Divergence> {
+        let recorder = Rc::new(RefCell::new(Recorder::default()));
+        computer.observe(Box::new(recorder.clone()));
+
+        let mut inputs = self.inputs.iter().peekable();
+
+        loop {
+            let count = recorder.borrow().session.trace.len();
+            let must_wait = self.waits.contains(&count) && !recorder.borrow().session.waits.contains(&count);
+
+            if !must_wait {
+                while let Some(&&(_, value)) = inputs.peek().filter(|&&&(at, _)| at <= count) {
+                    computer.push_input(value);
+                    inputs.next();
+                }
+            }
+
+            if count >= self.trace.len() && !must_wait {
+                break;
+            }
+
+            match computer.run_for(1) {
+                Ok(Status::Running) | Ok(Status::Breakpoint) => continue,
+                // Carry on only if the wait was expected, and so the input
+                // that ends it is still to be queued.
+                Ok(Status::AwaitingInput) if must_wait => continue,
+                _ => break
+            }
+        }
+
+        let replayed = recorder.borrow().session();
+        match self.diverges_from(&replayed) {
+            Some(divergence) => Err(divergence),
+            None => Ok(computer)
+        }
+    }
+
+    // The first instruction at which `actual` differs from this session.
+    fn diverges_from(&self, actual: &Session) -> Option<Divergence> {
+        let mut found: Vec<(usize, String)> = Vec::new();
+
+        if let Some(i) = (0..self.trace.len().max(actual.trace.len())).find(|&i| self.trace.get(i) != actual.trace.get(i)) {
+            found.push((i, match (self.trace.get(i), actual.trace.get(i)) {
+                (Some(expected), Some(got)) => format!("expected the instruction at {} but ran the one at {}.", expected, got),
+                (Some(expected), None) => format!("expected the instruction at {} but the run had stopped.", expected),
+                (_, got) => format!("ran the instruction at {} after the recording stopped.", got.unwrap())
+            }));
+        }
+
+        if let Some((i, reason)) = first_difference(&self.inputs, &actual.inputs, "input") {
+            found.push((i, reason));
+        }
+
+        if let Some((i, reason)) = first_difference(&self.outputs, &actual.outputs, "output") {
+            found.push((i, reason));
+        }
+
+        let waits = |session: &Session| session.waits.iter().map(|&at| (at, 0)).collect::<Vec<_>>();
+        if let Some((i, _)) = first_difference(&waits(self), &waits(actual), "wait") {
+            let reason = if self.waits.contains(&i) { "expected it to wait for input." } else { "it waited for input unexpectedly." };
+            found.push((i, reason.to_string()));
+        }
+
+        // Sorting is stable, so the trace wins a tie.
+        found.sort_by_key(|&(i, _)| i);
+        found.into_iter().next().map(|(instruction, reason)| Divergence {
+            instruction,
+            pointer: self.trace.get(instruction).copied(),
+            reason
+        })
+    }
+}
+
+// The instruction of the first event that differs, and how.
+fn first_difference(expected: &[(usize, i32)], actual: &[(usize, i32)], kind: &str) -> Option<(usize, String)> {
+    let i = (0..expected.len().max(actual.len())).find(|&i| expected.get(i) != actual.get(i))?;
+
+    Some(match (expected.get(i), actual.get(i)) {
+        (Some(&(at, value)), Some(&(got_at, got))) if at == got_at => (at, format!("expected {} {} but got {}.", kind, value, got)),
+        (Some(&(at, value)), Some(&(got_at, _))) if at < got_at => (at, format!("expected {} {} but there was none.", kind, value)),
+        (_, Some(&(got_at, got))) => (got_at, format!("got {} {} that was not recorded.", kind, got)),
+        (Some(&(at, value)), None) => (at, format!("expected {} {} but there was none.", kind, value)),
+        (None, None) => unreachable!()
+    })
+}
+
+fn numbers(value: &str) -> Result<Vec<usize>, String> {
+    value.split(',')
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(|value| value.parse().map_err(|err| format!("Failed to parse '{}' as a number: {}", value, err)))
+        .collect()
+}
+
+fn join<T: ToString>(values: impl Iterator<Item = T>) -> String {
+    values.map(|value| value.to_string()).collect::<Vec<_>>().join(",")
+}
+
+impl fmt::Display for Session {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "program = {}", join(self.program.iter()))?;
+        writeln!(f, "waits = {}", join(self.waits.iter()))?;
+
+        for (at, value) in &self.inputs {
+            writeln!(f, "input[{}] = {}", at, value)?;
+        }
+
+        for (at, value) in &self.outputs {
+            writeln!(f, "output[{}] = {}", at, value)?;
+        }
+
+        writeln!(f, "trace = {}", join(self.trace.iter()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::{Divergence, Recorder, Session};
+    use crate::computer::{Computer, Status};
+
+    // Doubles each input until it gets a 0.
+    const DOUBLER: &str = "3,15,1006,15,14,1002,15,2,16,4,16,1105,1,0,99,0,0";
+
+    fn record() -> Session {
+        let recorder = Rc::new(RefCell::new(Recorder::default()));
+        let mut computer = Computer::new(DOUBLER, vec![]).unwrap();
+        computer.observe(Box::new(recorder.clone()));
+
+        // Input arrives as it might interactively, sometimes late.
+        assert_eq!(computer.run(), Ok(Status::AwaitingInput));
+        computer.push_input(21);
+        computer.push_input(5);
+        assert_eq!(computer.run(), Ok(Status::AwaitingInput));
+        computer.push_input(0);
+        assert_eq!(computer.run(), Ok(Status::Halted));
+
+        let session = recorder.borrow().session();
+        session
+    }
+
+    #[test]
+    fn records_and_replays_a_session() {
+        let session = record();
+
+        assert_eq!(session.waits, vec![0, 10]);
+        assert_eq!(session.inputs, vec![(0, 21), (5, 5), (10, 0)]);
+        assert_eq!(session.outputs, vec![(3, 42), (8, 10)]);
+        assert_eq!(Session::parse(&session.to_string()), Ok(session.clone()));
+
+        let computer = session.replay(Computer::new(DOUBLER, vec![]).unwrap()).unwrap();
+        assert_eq!(computer.status(), Status::Halted);
+        assert_eq!(computer.outputs(), &[42, 10]);
+    }
+
+    #[test]
+    fn replays_a_session_that_stopped_early() {
+        let recorder = Rc::new(RefCell::new(Recorder::default()));
+        let mut computer = Computer::new(DOUBLER, vec![4]).unwrap();
+        computer.observe(Box::new(recorder.clone()));
+        assert_eq!(computer.run(), Ok(Status::AwaitingInput));
+
+        let session = recorder.borrow().session();
+        assert_eq!(session.waits, vec![5]);
+
+        let computer = session.replay(Computer::new(DOUBLER, vec![]).unwrap()).unwrap();
+        assert_eq!(computer.status(), Status::AwaitingInput);
+        assert_eq!(computer.outputs(), &[8]);
+    }
+
+    #[test]
+    fn reports_the_first_divergence() {
+        let session = record();
+
+        // Triples instead of doubling.
+        let changed = Computer::new(&DOUBLER.replace("1002,15,2", "1002,15,3"), vec![]).unwrap();
+        let divergence = session.replay(changed).unwrap_err();
+        assert_eq!(divergence, Divergence { instruction: 3, pointer: Some(9), reason: "expected output 42 but got 63.".to_string() });
+        assert_eq!(divergence.to_string(), "Diverged at instruction 3 (at 9): expected output 42 but got 63.");
+
+        // Loops again on a 0 rather than halting.
+        let changed = Computer::new(&DOUBLER.replace("1006,15,14", "1006,15,11"), vec![]).unwrap();
+        assert_eq!(
+            session.replay(changed).unwrap_err().to_string(),
+            "Diverged at instruction 12 (at 14): expected the instruction at 14 but ran the one at 11."
+        );
+    }
+}
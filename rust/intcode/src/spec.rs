@@ -0,0 +1,381 @@
+//! Conformance tests written as data rather than Rust.
+//!
+//! A spec file holds any number of cases, each starting with a
+//! `[case "name"]` header followed by `key = value` lines. `#` starts a
+//! comment, unless it is inside a quoted name.
+//!
+//! ```text
+//! # Outputs whether the input is equal to 8.
+//! [case "equal to 8"]
+//! program = 3,9,8,9,10,9,4,9,99,-1,8
+//! input = 8
+//! output = 1
+//! memory[9] = 1
+//! status = halted
+//! ```
+//!
+//! * `program`: the program, comma-separated.
+//! * `program_file`: instead of `program`, a file holding the program in any
+//!   of the formats in [`format`](../format/index.html), relative to the spec.
+//! * `input`: the values to input, comma-separated. Defaults to none.
+//! * `output`: every value that should be output, comma-separated. Required.
+//! * `memory[N]`: the value address `N` should hold once the program stops.
+//! * `status`: how the program should stop, either `halted` or
+//!   `awaiting input`.
+//! * `budget`: how many instructions the program may run before the case
+//!   fails. Defaults to `DEFAULT_BUDGET`.
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::computer::{Computer, Status};
+use crate::format::{self, Format};
+
+/// The extension spec files are found by in `run_dir`.
+pub const EXTENSION: &str = "spec";
+
+/// How many instructions a case may run, unless it sets a `budget`.
+pub const DEFAULT_BUDGET: usize = 1_000_000;
+
+/// A single case from a spec file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Case {
+    pub name: String,
+    pub program: Vec<i32>,
+    pub input: Vec<i32>,
+    pub output: Vec<i32>,
+    pub memory: Vec<(usize, i32)>,
+    pub status: Option<Status>,
+    pub budget: usize
+}
+
+/// The result of running a single case with `run_dir`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outcome {
+    pub file: PathBuf,
+    pub name: String,
+    /// Why the case failed, or `None` if it passed.
+    pub failure: Option<String>
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.failure {
+            None => write!(f, "PASS {}: {}", self.file.display(), self.name),
+            Some(failure) => write!(f, "FAIL {}: {}: {}", self.file.display(), self.name, failure)
+        }
+    }
+}
+
+impl Case {
+    /// Runs the case, returning a description of the first way it did not
+    /// do what was expected.
+    pub fn run(&self) -> Result<(), String> {
+        let mut computer = Computer::with_memory(self.program.clone(), self.input.clone());
+        let status = computer.run_for(self.budget).map_err(|err| format!("The program failed: {}", err))?;
+
+        if status == Status::Running {
+            return Err(format!("The program used up its budget of {} instructions.", self.budget));
+        }
+
+        if computer.outputs() != &self.output[..] {
+            return Err(format!("Expected output {:?} but got {:?}.", self.output, computer.outputs()));
+        }
+
+        for &(address, expected) in &self.memory {
+            match computer.memory().get(address) {
+                Some(&actual) if actual == expected => {},
+                Some(actual) => return Err(format!("Expected memory[{}] to be {} but it was {}.", address, expected, actual)),
+                None => return Err(format!("Expected memory[{}] to be {} but memory is only {} words long.", address, expected, computer.memory().len()))
+            }
+        }
+
+        match self.status {
+            Some(expected) if expected != status => {
+                Err(format!("Expected status '{}' but got '{}'.", status_name(expected), status_name(status)))
+            },
+            _ => Ok(())
+        }
+    }
+}
+
+/// Parses the cases in a spec, reading any `program_file` relative to `dir`.
+pub fn parse(text: &str, dir: &Path) -> Result<Vec<Case>, String> {
+    let mut cases = Vec::new();
+    let mut current: Option<Builder> = None;
+
+    for (i, line) in text.lines().enumerate() {
+        let line_number = i + 1;
+        let at = |reason: String| format!("line {}: {}", line_number, reason);
+        let line = strip_comment(line).trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        if line.starts_with('[') {
+            if let Some(builder) = current.take() {
+                cases.push(builder.build()?);
+            }
+
+            let name = line.strip_prefix("[case \"").and_then(|rest| rest.strip_suffix("\"]"))
+                .ok_or_else(|| at(format!("Expected a header like '[case \"name\"]' but got '{}'.", line)))?;
+            current = Some(Builder::new(name, line_number));
+            continue;
+        }
+
+        let builder = current.as_mut().ok_or_else(|| at("Expected a '[case \"name\"]' header first.".to_string()))?;
+        let (key, value) = line.split_once('=')
+            .map(|(key, value)| (key.trim(), value.trim()))
+            .ok_or_else(|| at(format!("Expected 'key = value' but got '{}'.", line)))?;
+
+        builder.set(key, value, dir).map_err(at)?;
+    }
+
+    if let Some(builder) = current {
+        cases.push(builder.build()?);
+    }
+
+    Ok(cases)
+}
+
+/// Loads every spec file in `dir`, in name order, returning each case along
+/// with the file it came from.
+pub fn load_dir(dir: &Path) -> Result<Vec<(PathBuf, Case)>, String> {
+    let entries = fs::read_dir(dir).map_err(|err| format!("Could not read {}: {}", dir.display(), err))?;
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == EXTENSION))
+        .collect();
+    files.sort();
+
+    let mut cases = Vec::new();
+
+    for file in files {
+        let text = fs::read_to_string(&file).map_err(|err| format!("Could not read {}: {}", file.display(), err))?;
+        let parsed = parse(&text, dir).map_err(|err| format!("{}, {}", file.display(), err))?;
+
+        cases.extend(parsed.into_iter().map(|case| (file.clone(), case)));
+    }
+
+    Ok(cases)
+}
+
+/// Loads every spec file in `dir`, in name order, and runs each of their
+/// cases.
+pub fn run_dir(dir: &Path) -> Result<Vec<Outcome>, String> {
+    let outcomes = load_dir(dir)?.into_iter()
+        .map(|(file, case)| Outcome {
+            file,
+            failure: case.run().err(),
+            name: case.name
+        })
+        .collect();
+
+    Ok(outcomes)
+}
+
+// Cuts a line off at the first `#` that isn't inside quotes.
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+
+    line
+}
+
+fn status_name(status: Status) -> &'static str {
+    match status {
+        Status::Running => "running",
+        Status::AwaitingInput => "awaiting input",
+        Status::Halted => "halted",
+        Status::Breakpoint => "breakpoint"
+    }
+}
+
+struct Builder {
+    case: Case,
+    line: usize,
+    has_program: bool,
+    has_output: bool
+}
+
+impl Builder {
+    fn new(name: &str, line: usize) -> Self {
+        Builder {
+            case: Case {
+                name: name.to_string(),
+                program: Vec::new(),
+                input: Vec::new(),
+                output: Vec::new(),
+                memory: Vec::new(),
+                status: None,
+                budget: DEFAULT_BUDGET
+            },
+            line,
+            has_program: false,
+            has_output: false
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &str, dir: &Path) -> Result<(), String> {
+        if (key == "program" || key == "program_file") && self.has_program {
+            return Err("The case already has a program.".to_string());
+        }
+
+        match key {
+            "program" => {
+                self.case.program = format::parse_as(Format::CommaSeparated, value.as_bytes()).map_err(|err| err.to_string())?;
+                self.has_program = true;
+            },
+            "program_file" => {
+                let path = dir.join(value);
+                let image = fs::read(&path).map_err(|err| format!("Could not read {}: {}", path.display(), err))?;
+                self.case.program = format::parse(&image).map_err(|err| err.to_string())?;
+                self.has_program = true;
+            },
+            "input" => self.case.input = values(value)?,
+            "budget" => {
+                self.case.budget = value.parse().map_err(|err| format!("Failed to parse budget '{}': {}", value, err))?;
+            },
+            "output" => {
+                self.case.output = values(value)?;
+                self.has_output = true;
+            },
+            "status" => {
+                self.case.status = match value {
+                    "halted" => Some(Status::Halted),
+                    "awaiting input" => Some(Status::AwaitingInput),
+                    _ => return Err(format!("Expected a status of 'halted' or 'awaiting input' but got '{}'.", value))
+                };
+            },
+            _ => {
+                let address = key.strip_prefix("memory[").and_then(|rest| rest.strip_suffix(']'))
+                    .and_then(|address| address.parse().ok())
+                    .ok_or_else(|| format!("Unknown key '{}'.", key))?;
+                let value = value.parse().map_err(|err| format!("Failed to parse '{}' as i32: {}", value, err))?;
+
+                self.case.memory.push((address, value));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn build(self) -> Result<Case, String> {
+        if !self.has_program {
+            return Err(format!("line {}: The case '{}' has no program.", self.line, self.case.name));
+        }
+
+        if !self.has_output {
+            return Err(format!("line {}: The case '{}' has no output.", self.line, self.case.name));
+        }
+
+        Ok(self.case)
+    }
+}
+
+fn values(value: &str) -> Result<Vec<i32>, String> {
+    value.split(',')
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(|value| value.parse().map_err(|err| format!("Failed to parse '{}' as i32: {}", value, err)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{parse, Case, DEFAULT_BUDGET};
+    use crate::computer::Status;
+
+    const SPEC: &str = "
+# Outputs whether the input is equal to 8.
+[case \"equal to 8\"]
+program = 3,9,8,9,10,9,4,9,99,-1,8
+input = 8
+output = 1
+memory[9] = 1   # The comparison is stored over the -1.
+status = halted
+
+[case \"waits for input\"]
+program = 3,0,99
+output =
+status = awaiting input
+budget = 10
+";
+
+    #[test]
+    fn parses_cases() {
+        let cases = parse(SPEC, Path::new(".")).unwrap();
+
+        assert_eq!(cases, vec![
+            Case {
+                name: "equal to 8".to_string(),
+                program: vec![3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8],
+                input: vec![8],
+                output: vec![1],
+                memory: vec![(9, 1)],
+                status: Some(Status::Halted),
+                budget: DEFAULT_BUDGET
+            },
+            Case {
+                name: "waits for input".to_string(),
+                program: vec![3, 0, 99],
+                input: vec![],
+                output: vec![],
+                memory: vec![],
+                status: Some(Status::AwaitingInput),
+                budget: 10
+            }
+        ]);
+        assert!(cases.iter().all(|case| case.run().is_ok()));
+    }
+
+    #[test]
+    fn allows_hashes_in_names() {
+        let cases = parse("[case \"issue #12\"]  # a comment\nprogram = 99\noutput =", Path::new(".")).unwrap();
+        assert_eq!(cases[0].name, "issue #12");
+    }
+
+    #[test]
+    fn reports_how_cases_fail() {
+        let failure = |spec: &str| parse(spec, Path::new(".")).unwrap()[0].run().unwrap_err();
+
+        assert_eq!(failure("[case \"a\"]\nprogram = 104,1,99\noutput = 2"), "Expected output [2] but got [1].");
+        assert_eq!(
+            failure("[case \"a\"]\nprogram = 104,1,99\noutput = 1\nmemory[1] = 2"),
+            "Expected memory[1] to be 2 but it was 1."
+        );
+        assert_eq!(
+            failure("[case \"a\"]\nprogram = 3,0,99\noutput =\nstatus = halted"),
+            "Expected status 'halted' but got 'awaiting input'."
+        );
+        assert_eq!(
+            failure("[case \"a\"]\nprogram = 4,9,99\noutput ="),
+            "The program failed: Instruction at 0 accessed invalid address 9."
+        );
+        assert_eq!(
+            failure("[case \"a\"]\nprogram = 1105,1,0\noutput =\nbudget = 50"),
+            "The program used up its budget of 50 instructions."
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_specs() {
+        let error = |spec: &str| parse(spec, Path::new(".")).unwrap_err();
+
+        assert_eq!(error("program = 99"), "line 1: Expected a '[case \"name\"]' header first.");
+        assert_eq!(error("[case \"a\"]\nprogram = 99\noutput = x"), "line 3: Failed to parse 'x' as i32: invalid digit found in string");
+        assert_eq!(error("[case \"a\"]\nprogram = 99\nspeed = 3"), "line 3: Unknown key 'speed'.");
+        assert_eq!(error("[case \"a\"]\nprogram = 99\n[case \"b\"]"), "line 1: The case 'a' has no output.");
+    }
+}
@@ -0,0 +1,287 @@
+//! Running a program over every combination of patches and inputs.
+
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::computer::{Computer, Status};
+use crate::error::Error;
+use crate::image::Image;
+
+#[derive(Debug, Clone)]
+enum Axis {
+    Patch(usize, RangeInclusive<i32>),
+    Input(RangeInclusive<i32>)
+}
+
+impl Axis {
+    fn values(&self) -> &RangeInclusive<i32> {
+        match self {
+            Axis::Patch(_, values) | Axis::Input(values) => values
+        }
+    }
+
+    fn len(&self) -> usize {
+        len(self.values())
+    }
+}
+
+fn len(values: &RangeInclusive<i32>) -> usize {
+    if values.is_empty() {
+        0
+    } else {
+        (*values.end() as i64 - *values.start() as i64 + 1) as usize
+    }
+}
+
+// The number of ways to pick one value from each of `domain`, or `None` if
+// there are too many to count.
+pub(crate) fn combinations<'a, I>(domain: I) -> Option<usize>
+    where I: IntoIterator<Item = &'a RangeInclusive<i32>> {
+
+    let lens: Vec<usize> = domain.into_iter().map(len).collect();
+
+    if lens.contains(&0) {
+        Some(0)
+    } else {
+        lens.into_iter().try_fold(1, usize::checked_mul)
+    }
+}
+
+/// Runs copies of a program over every combination of values for a set of
+/// axes, looking for the combinations whose final state matches a predicate.
+///
+/// A combination holds one value per axis, in the order the axes were added,
+/// and the last axis varies fastest. Every run shares the same parsed image,
+/// copying only the pages it writes to. Runs that fail, or that use up their
+/// budget, never match. Sweeps with more combinations than fit in a `usize`
+/// fail with `Error::DomainTooLarge`.
+///
+/// ```
+/// use intcode::sweep::Sweep;
+///
+/// // Multiplies the words at 1 and 2 into 0.
+/// let sweep = Sweep::new(vec![1102, 0, 0, 0, 99]).patch(1, 0..=9).patch(2, 0..=9);
+/// let found = sweep.first(|computer| computer.memory()[0] == 12).unwrap();
+///
+/// assert_eq!(found, Some(vec![2, 6]));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Sweep {
+    image: Image,
+    axes: Vec<Axis>,
+    threads: usize,
+    budget: usize
+}
+
+impl Sweep {
+    /// Creates a sweep over `memory`, as `with_image` does.
+    pub fn new(memory: Vec<i32>) -> Self {
+        Sweep::with_image(Image::new(memory))
+    }
+
+    /// Creates a sweep over an image that has already been parsed, with no
+    /// axes, using as many threads as there are CPUs and a budget of 100,000
+    /// instructions per run.
+    pub fn with_image(image: Image) -> Self {
+        Sweep {
+            image,
+            axes: Vec::new(),
+            threads: thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            budget: 100_000
+        }
+    }
+
+    /// Varies the word at `address` over `values`.
+    pub fn patch(mut self, address: usize, values: RangeInclusive<i32>) -> Self {
+        self.axes.push(Axis::Patch(address, values));
+        self
+    }
+
+    /// Varies an input value over `values`. Inputs are queued in the order
+    /// their axes were added.
+    pub fn input(mut self, values: RangeInclusive<i32>) -> Self {
+        self.axes.push(Axis::Input(values));
+        self
+    }
+
+    /// Sets how many worker threads to run on.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Sets how many instructions each run may execute.
+    pub fn budget(mut self, budget: usize) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// The number of combinations that will be tried, or `None` if there are
+    /// more than fit in a `usize`.
+    pub fn combinations(&self) -> Option<usize> {
+        combinations(self.axes.iter().map(Axis::values))
+    }
+
+    /// Finds the first combination, in order, that matches `predicate`, or
+    /// `None` if there is no solution. The answer does not depend on the
+    /// number of threads.
+    pub fn first<P>(&self, predicate: P) -> Result<Option<Vec<i32>>, Error>
+        where P: Fn(&Computer) -> bool + Sync {
+
+        let found = self.search(&predicate, true)?;
+        Ok(found.first().map(|&index| self.combination(index)))
+    }
+
+    /// Finds every combination that matches `predicate`, in order.
+    pub fn all<P>(&self, predicate: P) -> Result<Vec<Vec<i32>>, Error>
+        where P: Fn(&Computer) -> bool + Sync {
+
+        let found = self.search(&predicate, false)?;
+        Ok(found.into_iter().map(|index| self.combination(index)).collect())
+    }
+
+    fn search<P>(&self, predicate: &P, first: bool) -> Result<Vec<usize>, Error>
+        where P: Fn(&Computer) -> bool + Sync {
+
+        let len = self.image.words().len();
+        for axis in &self.axes {
+            if let Axis::Patch(address, _) = *axis {
+                if address >= len {
+                    return Err(Error::InvalidPatch { address, len });
+                }
+            }
+        }
+
+        let total = self.combinations().ok_or(Error::DomainTooLarge)?;
+        let next = AtomicUsize::new(0);
+        // The lowest matching index so far. Combinations are handed out in
+        // order, so once one matches nothing after it needs trying.
+        let lowest = AtomicUsize::new(usize::MAX);
+        let found = Mutex::new(Vec::new());
+
+        thread::scope(|scope| {
+            for _ in 0..self.threads {
+                scope.spawn(|| loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    if index >= total || (first && index > lowest.load(Ordering::Relaxed)) {
+                        break;
+                    }
+
+                    if self.matches(index, predicate) {
+                        lowest.fetch_min(index, Ordering::Relaxed);
+                        found.lock().unwrap().push(index);
+                    }
+                });
+            }
+        });
+
+        let mut found = found.into_inner().unwrap();
+        found.sort_unstable();
+
+        if first {
+            found.truncate(1);
+        }
+
+        Ok(found)
+    }
+
+    fn combination(&self, mut index: usize) -> Vec<i32> {
+        let mut values = vec![0; self.axes.len()];
+
+        for (value, axis) in values.iter_mut().zip(&self.axes).rev() {
+            let len = axis.len();
+            *value = *axis.values().start() + (index % len) as i32;
+            index /= len;
+        }
+
+        values
+    }
+
+    fn matches<P>(&self, index: usize, predicate: &P) -> bool
+        where P: Fn(&Computer) -> bool {
+
+        let values = self.combination(index);
+        let inputs = self.axes.iter().zip(&values)
+            .filter(|(axis, _)| matches!(axis, Axis::Input(_)))
+            .map(|(_, &value)| value)
+            .collect();
+
+        let mut computer = Computer::with_image(self.image.clone(), inputs);
+        for (axis, &value) in self.axes.iter().zip(&values) {
+            if let Axis::Patch(address, _) = *axis {
+                // Addresses were checked before the search started.
+                computer.patch(address, value).unwrap();
+            }
+        }
+
+        matches!(computer.run_for(self.budget), Ok(Status::Halted) | Ok(Status::AwaitingInput)) && predicate(&computer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::RangeInclusive;
+
+    use super::Sweep;
+    use crate::computer::Status;
+    use crate::error::Error;
+
+    // Adds the words at 1 and 2 into 0.
+    const ADD: [i32; 5] = [1101, 0, 0, 0, 99];
+
+    #[test]
+    fn finds_the_first_match_on_any_number_of_threads() {
+        for threads in 1..=4 {
+            let sweep = Sweep::new(ADD.to_vec()).patch(1, 0..=4).patch(2, 0..=4).threads(threads);
+
+            assert_eq!(sweep.first(|computer| computer.memory()[0] == 3), Ok(Some(vec![0, 3])));
+            assert_eq!(sweep.first(|computer| computer.memory()[0] == 100), Ok(None));
+        }
+    }
+
+    #[test]
+    fn finds_every_match_over_inputs() {
+        // Outputs whether the two inputs are equal.
+        let sweep = Sweep::new(vec![3, 11, 3, 12, 8, 11, 12, 13, 4, 13, 99, 0, 0, 0])
+            .input(1..=3)
+            .input(1..=3)
+            .threads(3);
+
+        assert_eq!(sweep.combinations(), Some(9));
+        assert_eq!(
+            sweep.all(|computer| computer.last_output() == Some(1)),
+            Ok(vec![vec![1, 1], vec![2, 2], vec![3, 3]])
+        );
+        assert_eq!(
+            sweep.all(|computer| computer.status() == Status::AwaitingInput),
+            Ok(vec![])
+        );
+    }
+
+    #[test]
+    fn runs_that_use_up_their_budget_never_match() {
+        // Jumps to the address at 2, which loops forever if it is 0.
+        let sweep = Sweep::new(vec![1105, 1, 0, 99]).patch(2, 0..=3).budget(100);
+        assert_eq!(sweep.all(|_| true), Ok(vec![vec![3]]));
+    }
+
+    #[test]
+    fn rejects_patches_outside_of_memory() {
+        let sweep = Sweep::new(ADD.to_vec()).patch(5, 0..=1);
+        assert_eq!(sweep.first(|_| true), Err(Error::InvalidPatch { address: 5, len: 5 }));
+    }
+
+    #[test]
+    fn rejects_domains_too_large_to_count() {
+        let sweep = Sweep::new(ADD.to_vec()).input(i32::MIN..=i32::MAX).input(i32::MIN..=i32::MAX).input(0..=1);
+        assert_eq!(sweep.combinations(), None);
+        assert_eq!(sweep.first(|_| true), Err(Error::DomainTooLarge));
+
+        // Any empty axis leaves nothing to try, however big the others are.
+        let sweep = sweep.input(RangeInclusive::new(1, 0));
+        assert_eq!(sweep.combinations(), Some(0));
+        assert_eq!(sweep.first(|_| true), Ok(None));
+    }
+}
@@ -0,0 +1,162 @@
+//! Counting the cost of a run in cycles, independent of the host machine.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use crate::decoder::{Opcode, OpcodeWithParamModes, ParamMode};
+
+/// How many cycles each instruction costs: a cost for its opcode, plus a
+/// cost for each param it reads depending on that param's mode.
+///
+/// By default every opcode costs 1 cycle and every position mode read costs
+/// 1 more, for the extra memory access. Immediate mode reads are free.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CostTable {
+    opcodes: HashMap<Opcode, u64>,
+    modes: HashMap<ParamMode, u64>
+}
+
+impl Default for CostTable {
+    fn default() -> Self {
+        let opcodes = [
+            Opcode::Add,
+            Opcode::Multiply,
+            Opcode::Input,
+            Opcode::Output,
+            Opcode::JumpIfTrue,
+            Opcode::JumpIfFalse,
+            Opcode::LessThan,
+            Opcode::Equals,
+            Opcode::Halt
+        ];
+
+        let mut modes = HashMap::new();
+        modes.insert(ParamMode::PositionMode, 1);
+        modes.insert(ParamMode::ImmediateMode, 0);
+
+        CostTable {
+            opcodes: opcodes.iter().map(|&opcode| (opcode, 1)).collect(),
+            modes
+        }
+    }
+}
+
+impl CostTable {
+    /// Sets the cost of executing `opcode`.
+    pub fn opcode(mut self, opcode: Opcode, cycles: u64) -> Self {
+        self.opcodes.insert(opcode, cycles);
+        self
+    }
+
+    /// Sets the cost of each param read in `mode`.
+    pub fn mode(mut self, mode: ParamMode, cycles: u64) -> Self {
+        self.modes.insert(mode, cycles);
+        self
+    }
+
+    /// The cycles that executing `instruction` costs.
+    pub fn cost(&self, instruction: &OpcodeWithParamModes) -> u64 {
+        let writable = instruction.opcode.writable_params();
+        let reads: u64 = (0..instruction.opcode.num_params())
+            .filter(|i| !writable.contains(&(i + 1)))
+            .map(|i| self.modes[&instruction.param_modes[i]])
+            .sum();
+
+        self.opcodes[&instruction.opcode] + reads
+    }
+}
+
+/// How many of an opcode were executed, and the cycles they took.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct OpcodeTiming {
+    pub count: u64,
+    pub cycles: u64
+}
+
+/// The instructions a `Computer` has executed and the cycles they cost,
+/// overall and by opcode. Only instructions that complete are counted, so an
+/// input instruction waiting for a value costs nothing until it is retried.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Timing {
+    pub instructions: u64,
+    pub cycles: u64,
+    pub by_opcode: BTreeMap<Opcode, OpcodeTiming>
+}
+
+impl Timing {
+    pub(crate) fn record(&mut self, opcode: Opcode, cycles: u64) {
+        let timing = self.by_opcode.entry(opcode).or_default();
+        timing.count += 1;
+        timing.cycles += cycles;
+
+        self.instructions += 1;
+        self.cycles += cycles;
+    }
+}
+
+impl fmt::Display for Timing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} instructions in {} cycles", self.instructions, self.cycles)?;
+
+        for (opcode, timing) in &self.by_opcode {
+            writeln!(f, "  {:<4} {:>8} {:>10}", opcode.mnemonic(), timing.count, timing.cycles)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CostTable, OpcodeTiming};
+    use crate::computer::{Computer, Status, MAX_FREE_STEPS};
+    use crate::decoder::{Opcode, ParamMode};
+
+    #[test]
+    fn counts_cycles_by_opcode_and_mode() {
+        let mut computer = Computer::new("1002,7,3,7,4,7,99,14", vec![]).unwrap();
+        assert_eq!(computer.run(), Ok(Status::Halted));
+
+        // mul with one position read, out with one, then hlt.
+        let timing = computer.timing();
+        assert_eq!((timing.instructions, timing.cycles), (3, 5));
+        assert_eq!(timing.by_opcode[&Opcode::Multiply], OpcodeTiming { count: 1, cycles: 2 });
+        assert_eq!(timing.to_string(), "3 instructions in 5 cycles\n  mul         1          2\n  out         1          2\n  hlt         1          1\n");
+
+        let costs = CostTable::default().opcode(Opcode::Multiply, 10).mode(ParamMode::ImmediateMode, 1);
+        let mut computer = Computer::new("1002,7,3,7,4,7,99,14", vec![]).unwrap().with_costs(costs);
+        computer.run().unwrap();
+        assert_eq!(computer.timing().cycles, 12 + 2 + 1);
+    }
+
+    #[test]
+    fn compares_equivalent_programs() {
+        // Both output 42, but the second keeps its operands in memory.
+        let immediate = Computer::new("1102,6,7,9,4,9,99,0,0,0", vec![]).unwrap();
+        let position = Computer::new("2,10,11,9,4,9,99,0,0,0,6,7", vec![]).unwrap();
+
+        let runs: Vec<(Option<i32>, u64)> = vec![immediate, position].into_iter()
+            .map(|mut computer| computer.exec_with_cycles())
+            .collect();
+
+        assert_eq!(runs, vec![(Some(42), 4), (Some(42), 6)]);
+    }
+
+    #[test]
+    fn runs_for_a_budget_of_cycles() {
+        let mut computer = Computer::new("4,0,4,0,4,0,99", vec![]).unwrap();
+
+        // Each output costs 2 cycles, so 3 cycles only fits one and a half.
+        assert_eq!(computer.run_for_cycles(3), Ok(Status::Running));
+        assert_eq!(computer.outputs(), &[4, 4]);
+        // A budget of `u64::MAX` runs to the end, whatever has been spent.
+        assert_eq!(computer.run_for_cycles(u64::MAX), Ok(Status::Halted));
+        assert_eq!(computer.timing().cycles, 7);
+
+        // A loop that costs nothing gives up rather than running forever.
+        let free = CostTable::default().opcode(Opcode::JumpIfTrue, 0).mode(ParamMode::PositionMode, 0);
+        let mut computer = Computer::new("1105,1,0", vec![]).unwrap().with_costs(free);
+        assert_eq!(computer.run_for_cycles(1), Ok(Status::Running));
+        assert_eq!(computer.timing().instructions, MAX_FREE_STEPS as u64);
+    }
+}
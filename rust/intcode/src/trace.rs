@@ -0,0 +1,295 @@
+//! Exporting runs as Chrome Trace Event JSON, for `chrome://tracing` or
+//! Perfetto.
+
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use crate::computer::Computer;
+use crate::decoder::{Opcode, OpcodeWithParamModes};
+use crate::observer::{Control, Observer};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Event {
+    Span { category: &'static str, address: usize, start: u64, end: u64 },
+    Instant { name: &'static str, at: u64, value: i32 },
+    Counter { at: u64, cells: usize }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Loop {
+    header: usize,
+    latch: usize,
+    start: u64
+}
+
+/// A clock shared by the tracers of machines that take turns to run, eg. on
+/// a `Scheduler`, so that their tracks line up.
+///
+/// It counts the cycles spent by every machine sharing it, so each event is
+/// stamped with how far into the run as a whole it happened, and events at
+/// the same time on different tracks really did happen together.
+#[derive(Debug, Clone, Default)]
+pub struct Timeline(Rc<Cell<u64>>);
+
+/// Records a run of one machine as trace events, see `to_json`.
+///
+/// Time is measured in cycles, as counted by the machine's `CostTable`, with
+/// one cycle shown as one microsecond. Each machine has its own timeline,
+/// starting from 0, unless its tracer shares a `Timeline` with others; the
+/// tracks of machines that don't share one are not aligned with each other.
+///
+/// * Every run of instructions up to a jump, or a halt, is shown as a
+///   `block` span, named after the address it started at.
+/// * Every time a jump goes backwards, the code from its target to the jump
+///   is taken to be a loop. It is shown as a `loop` span from when the
+///   target was last entered until the machine runs code outside it.
+/// * Inputs and outputs are shown as instant events.
+/// * The number of different cells written to so far is shown as a
+///   `memory` counter.
+#[derive(Debug)]
+pub struct Tracer {
+    machine: usize,
+    name: String,
+    timeline: Timeline,
+    // The machine's own cycle count when it was last looked at.
+    cycles: u64,
+    now: u64,
+    block: Option<(usize, u64)>,
+    block_starts: HashMap<usize, u64>,
+    loops: Vec<Loop>,
+    written: HashSet<usize>,
+    events: Vec<Event>
+}
+
+impl Tracer {
+    /// Creates a tracer for the machine numbered `machine`, shown as `name`.
+    pub fn new(machine: usize, name: &str) -> Self {
+        Tracer {
+            machine,
+            name: name.to_string(),
+            timeline: Timeline::default(),
+            cycles: 0,
+            now: 0,
+            block: None,
+            block_starts: HashMap::new(),
+            loops: Vec::new(),
+            written: HashSet::new(),
+            events: Vec::new()
+        }
+    }
+
+    /// Stamps events on `timeline`, shared with other tracers, rather than on
+    /// a timeline of the machine's own.
+    pub fn on(mut self, timeline: &Timeline) -> Self {
+        self.timeline = timeline.clone();
+        self
+    }
+
+    // Moves the timeline on by the cycles the machine has spent since it was
+    // last looked at.
+    fn tick(&mut self, computer: &Computer) {
+        let cycles = computer.timing().cycles;
+        let clock = &self.timeline.0;
+
+        clock.set(clock.get() + cycles.saturating_sub(self.cycles));
+        self.cycles = cycles;
+        self.now = clock.get();
+    }
+
+    fn close_block(&mut self) {
+        if let Some((address, start)) = self.block.take() {
+            self.events.push(Event::Span { category: "block", address, start, end: self.now });
+        }
+    }
+
+    fn close_loop(&mut self) {
+        if let Some(l) = self.loops.pop() {
+            self.events.push(Event::Span { category: "loop", address: l.header, start: l.start, end: self.now });
+        }
+    }
+
+    // Every event so far, with anything still open closed at the current
+    // time.
+    fn events(&self) -> Vec<Event> {
+        let mut events = self.events.clone();
+
+        if let Some((address, start)) = self.block {
+            events.push(Event::Span { category: "block", address, start, end: self.now });
+        }
+
+        for l in self.loops.iter().rev() {
+            events.push(Event::Span { category: "loop", address: l.header, start: l.start, end: self.now });
+        }
+
+        events
+    }
+}
+
+impl Observer for Tracer {
+    fn before(&mut self, computer: &Computer, _instruction: &OpcodeWithParamModes) -> Control {
+        let pointer = computer.pointer();
+        self.tick(computer);
+
+        while let Some(l) = self.loops.last() {
+            if pointer >= l.header && pointer <= l.latch {
+                break;
+            }
+            self.close_loop();
+        }
+
+        if self.block.is_none() {
+            self.block = Some((pointer, self.now));
+            self.block_starts.insert(pointer, self.now);
+        }
+
+        Control::Continue
+    }
+
+    fn after(&mut self, computer: &Computer, pointer: usize, instruction: &OpcodeWithParamModes) {
+        self.tick(computer);
+
+        if let Opcode::JumpIfTrue | Opcode::JumpIfFalse = instruction.opcode {
+            self.close_block();
+
+            let target = computer.pointer();
+            let taken = target != pointer + instruction.opcode.num_params() + 1;
+            let current = self.loops.last().map(|l| (l.header, l.latch));
+
+            if taken && target <= pointer && current != Some((target, pointer)) {
+                let start = self.block_starts.get(&target).copied().unwrap_or(self.now);
+                self.loops.push(Loop { header: target, latch: pointer, start });
+            }
+        }
+    }
+
+    fn write(&mut self, address: usize, _value: i32) {
+        if self.written.insert(address) {
+            self.events.push(Event::Counter { at: self.now, cells: self.written.len() });
+        }
+    }
+
+    fn input(&mut self, value: i32) {
+        self.events.push(Event::Instant { name: "input", at: self.now, value });
+    }
+
+    fn output(&mut self, value: i32) {
+        self.events.push(Event::Instant { name: "output", at: self.now, value });
+    }
+
+    fn halt(&mut self, computer: &Computer) {
+        self.tick(computer);
+        self.close_block();
+
+        while !self.loops.is_empty() {
+            self.close_loop();
+        }
+    }
+}
+
+/// Renders the runs recorded by `tracers` as a single trace, with one track
+/// for each machine.
+pub fn to_json(tracers: &[&Tracer]) -> String {
+    let mut events = Vec::new();
+
+    for tracer in tracers {
+        let pid = tracer.machine;
+
+        events.push(format!(
+            "{{\"name\":\"process_name\",\"ph\":\"M\",\"pid\":{},\"tid\":0,\"args\":{{\"name\":\"{}\"}}}}",
+            pid, escape(&tracer.name)
+        ));
+
+        for event in tracer.events() {
+            events.push(match event {
+                Event::Span { category, address, start, end } => format!(
+                    "{{\"name\":\"{} {}\",\"cat\":\"{}\",\"ph\":\"X\",\"pid\":{},\"tid\":0,\"ts\":{},\"dur\":{}}}",
+                    category, address, category, pid, start, end - start
+                ),
+                Event::Instant { name, at, value } => format!(
+                    "{{\"name\":\"{}\",\"cat\":\"io\",\"ph\":\"i\",\"s\":\"t\",\"pid\":{},\"tid\":0,\"ts\":{},\"args\":{{\"value\":{}}}}}",
+                    name, pid, at, value
+                ),
+                Event::Counter { at, cells } => format!(
+                    "{{\"name\":\"memory\",\"ph\":\"C\",\"pid\":{},\"tid\":0,\"ts\":{},\"args\":{{\"cells written\":{}}}}}",
+                    pid, at, cells
+                )
+            });
+        }
+    }
+
+    format!("{{\"traceEvents\":[\n{}\n]}}\n", events.join(",\n"))
+}
+
+fn escape(text: &str) -> String {
+    text.chars().flat_map(|c| match c {
+        '"' | '\\' => vec!['\\', c],
+        c if c.is_control() => format!("\\u{:04x}", c as u32).chars().collect(),
+        c => vec![c]
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::{to_json, Timeline, Tracer};
+    use crate::computer::Computer;
+    use crate::scheduler::Scheduler;
+
+    #[test]
+    fn traces_blocks_loops_and_io() {
+        // Counts down from 3, outputting each number.
+        let mut memory = vec![1001, 20, -1, 20, 4, 20, 1005, 20, 0, 99];
+        memory.resize(21, 0);
+        memory[20] = 3;
+
+        let tracer = Rc::new(RefCell::new(Tracer::new(0, "countdown")));
+        let mut computer = Computer::with_memory(memory, vec![]);
+        computer.observe(Box::new(tracer.clone()));
+        computer.exec();
+
+        let json = to_json(&[&tracer.borrow()]);
+        let lines: Vec<&str> = json.lines().collect();
+
+        assert_eq!(lines[0], "{\"traceEvents\":[");
+        assert_eq!(lines[1], "{\"name\":\"process_name\",\"ph\":\"M\",\"pid\":0,\"tid\":0,\"args\":{\"name\":\"countdown\"}},");
+        assert_eq!(lines[2], "{\"name\":\"memory\",\"ph\":\"C\",\"pid\":0,\"tid\":0,\"ts\":0,\"args\":{\"cells written\":1}},");
+        assert_eq!(lines[3], "{\"name\":\"output\",\"cat\":\"io\",\"ph\":\"i\",\"s\":\"t\",\"pid\":0,\"tid\":0,\"ts\":2,\"args\":{\"value\":2}},");
+        assert_eq!(lines[4], "{\"name\":\"block 0\",\"cat\":\"block\",\"ph\":\"X\",\"pid\":0,\"tid\":0,\"ts\":0,\"dur\":6},");
+        assert!(json.contains("{\"name\":\"loop 0\",\"cat\":\"loop\",\"ph\":\"X\",\"pid\":0,\"tid\":0,\"ts\":0,\"dur\":18},"));
+        assert!(json.contains("{\"name\":\"block 9\",\"cat\":\"block\",\"ph\":\"X\",\"pid\":0,\"tid\":0,\"ts\":18,\"dur\":1}\n]}"));
+        assert_eq!(json.matches("\"name\":\"block 0\"").count(), 3);
+        assert_eq!(json.matches("\"name\":\"output\"").count(), 3);
+    }
+
+    #[test]
+    fn traces_a_track_per_machine() {
+        let mut scheduler = Scheduler::new(10, 1);
+        let timeline = Timeline::default();
+        let tracers: Vec<Rc<RefCell<Tracer>>> = (0..2)
+            .map(|machine| Tracer::new(machine, &format!("machine \"{}\"", machine)).on(&timeline))
+            .map(|tracer| Rc::new(RefCell::new(tracer)))
+            .collect();
+
+        // The first machine sends 5 to the second, which outputs it to 9.
+        for (tracer, program) in tracers.iter().zip(&["104,1,104,5,99", "3,5,104,9,4,5,99"]) {
+            let mut computer = Computer::new(program, vec![]).unwrap();
+            computer.observe(Box::new(tracer.clone()));
+            scheduler.add(computer);
+        }
+
+        scheduler.run(10).unwrap();
+
+        let borrowed: Vec<_> = tracers.iter().map(|tracer| tracer.borrow()).collect();
+        let json = to_json(&borrowed.iter().map(|tracer| &**tracer).collect::<Vec<_>>());
+
+        assert!(json.contains("\"pid\":0,\"tid\":0,\"args\":{\"name\":\"machine \\\"0\\\"\"}"));
+        assert!(json.contains("\"pid\":1,\"tid\":0,\"args\":{\"name\":\"machine \\\"1\\\"\"}"));
+        // The second machine only gets its input once the first has run its
+        // 3 cycles, and the shared timeline shows that.
+        assert!(json.contains("{\"name\":\"input\",\"cat\":\"io\",\"ph\":\"i\",\"s\":\"t\",\"pid\":1,\"tid\":0,\"ts\":3,\"args\":{\"value\":5}}"));
+        assert_eq!(json.matches("\"name\":\"output\"").count(), 4);
+    }
+}